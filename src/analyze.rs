@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::{
+	binding::Formula,
+	completion::{critical_pairs, joinable_within, module_equations, normalize_limited, CriticalPair, Rule, TermOrder},
+	expr::*,
+	parsing,
+	print::to_string,
+	proof::FormulaPosition,
+};
+
+/// Что не так с правилами модулей.
+#[derive(Debug, Error)]
+pub enum Issue {
+	#[error("`{rule}` is not decreasing in the order, `{}` is not greater than `{}`", to_string(left), to_string(right))]
+	NotDecreasing { rule: parsing::FormulaPosition, left: Expression, right: Expression },
+	#[error("left side of `{rule}` is a bare pattern, it matches every expression")]
+	BarePattern { rule: parsing::FormulaPosition },
+	#[error(
+		"critical pair of `{first}` and `{second}` is not joinable: `{}` is rewritten to `{}` and `{}`, their normal forms are `{}` and `{}`",
+		to_string(&pair.overlap), to_string(&pair.left), to_string(&pair.right),
+		to_string(left_normal), to_string(right_normal)
+	)]
+	NotJoinable {
+		first: parsing::FormulaPosition,
+		second: parsing::FormulaPosition,
+		pair: CriticalPair,
+		left_normal: Expression,
+		right_normal: Expression,
+	},
+	#[error(
+		"critical pair of `{first}` and `{second}` is not joined in {steps} steps: `{}` is rewritten to `{}` and `{}`",
+		to_string(&pair.overlap), to_string(&pair.left), to_string(&pair.right)
+	)]
+	NotJoined { first: parsing::FormulaPosition, second: parsing::FormulaPosition, pair: CriticalPair, steps: usize },
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+	/// Формулы модулей, ориентированные в правила.
+	pub rules: Vec<(parsing::FormulaPosition, Rule)>,
+	/// Формулы с условиями и с `$f`, они не проверяются.
+	pub skipped: Vec<FormulaPosition>,
+	/// Сколько нетривиальных критических пар проверено.
+	pub critical_pairs: usize,
+	pub issues: Vec<Issue>,
+}
+
+impl Analysis {
+	/// Все правила уменьшают выражение в порядке, значит, переписывание ими всегда останавливается.
+	pub fn is_terminating(&self) -> bool {
+		!self.issues.iter().any(|issue| matches!(issue, Issue::NotDecreasing { .. } | Issue::BarePattern { .. }))
+	}
+
+	/// Все критические пары сводятся. Вместе с завершаемостью это значит, что у каждого выражения одна нормальная форма.
+	pub fn is_locally_confluent(&self) -> bool {
+		!self.issues.iter().any(|issue| matches!(issue, Issue::NotJoinable { .. } | Issue::NotJoined { .. }))
+	}
+}
+
+fn is_pattern(expr: &Expression) -> bool { matches!(expr.0, ExpressionMeta::Pattern { .. }) }
+
+/// Проверяет формулы модулей `modules` как систему правил. Формулы ориентируются слева направо, кроме `reversed`. Правило должно уменьшать выражение в порядке `order`, а каждая критическая пара должна сводиться: обе её стороны переписываются в одно и то же выражение не больше чем за `steps` переписываний каждая. Если у сторон нет нормальных форм за `steps` переписываний, общее выражение ищется переписыванием обеих сторон в ширину, пока не будет получено `steps` выражений.
+///
+/// Правила, у которых слева стоит один паттерн, применяются к любому выражению, поэтому в критических парах они не участвуют, а только попадают в предупреждения.
+pub fn analyze(
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	modules: &[String],
+	reversed: &[FormulaPosition],
+	order: &TermOrder,
+	steps: usize,
+) -> Analysis {
+	let (equations, skipped) = module_equations(global_formulas, modules);
+	let rules: Vec<(parsing::FormulaPosition, Rule)> = equations
+		.into_iter()
+		.map(|(position, left, right)| {
+			let left_to_right = !reversed.contains(&position);
+			let rule = if left_to_right { Rule { left, right } } else { Rule { left: right, right: left } };
+			let FormulaPosition { module_name, position } = position;
+			(parsing::FormulaPosition { module_name, position, left_to_right, lemma: false }, rule)
+		})
+		.collect();
+
+	let mut issues = Vec::new();
+	for (name, rule) in &rules {
+		if is_pattern(&rule.left) {
+			issues.push(Issue::BarePattern { rule: name.clone() });
+		}
+		if !order.greater(&rule.left, &rule.right) {
+			let (left, right) = (rule.left.clone(), rule.right.clone());
+			issues.push(Issue::NotDecreasing { rule: name.clone(), left, right });
+		}
+	}
+
+	let (names, active): (Vec<_>, Vec<_>) = rules.iter().filter(|(_, rule)| !is_pattern(&rule.left)).cloned().unzip();
+	let mut seen: Vec<CriticalPair> = Vec::new();
+	for (i, first) in active.iter().enumerate() {
+		for (j, second) in active.iter().enumerate() {
+			for pair in critical_pairs(first, second, i == j) {
+				let swapped = CriticalPair { left: pair.right.clone(), right: pair.left.clone(), ..pair.clone() };
+				if pair.left == pair.right || seen.contains(&pair) || seen.contains(&swapped) {
+					continue;
+				}
+				seen.push(pair.clone());
+
+				let (first, second) = (names[i].clone(), names[j].clone());
+				let (mut left_steps, mut right_steps) = (steps, steps);
+				let left_normal = normalize_limited(&pair.left, &active, &mut left_steps);
+				let right_normal = normalize_limited(&pair.right, &active, &mut right_steps);
+				match (left_normal, right_normal) {
+					(Some(left_normal), Some(right_normal)) if left_normal == right_normal => {},
+					(Some(left_normal), Some(right_normal)) => {
+						issues.push(Issue::NotJoinable { first, second, pair, left_normal, right_normal })
+					},
+					// Нормальной формы может не быть, если правила не уменьшают выражение, тогда общее выражение ищется в ширину.
+					_ if joinable_within(&pair.left, &pair.right, &active, steps) => {},
+					_ => issues.push(Issue::NotJoined { first, second, pair, steps }),
+				}
+			}
+		}
+	}
+
+	Analysis { rules, skipped, critical_pairs: seen.len(), issues }
+}
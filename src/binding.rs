@@ -1,4 +1,7 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt,
+};
 
 use thiserror::Error;
 
@@ -30,6 +33,13 @@ pub enum FormulaError {
 		left_side: Vec<AnyFunctionNames>,
 		right_side: Vec<AnyFunctionNames>,
 	},
+	#[error("hypotheses use patterns [{}] and functions [{}], which are not presented in formula", patterns.iter().joined_by(", "), functions.iter().joined_by(", "))]
+	UnknownPatternsInHypotheses {
+		patterns: Vec<String>,
+		functions: Vec<AnyFunctionNames>,
+	},
+	#[error("patterns [{}] are metavariables, they can be written only in proof steps", names.iter().joined_by(", "))]
+	MetavariablesInFormula { names: Vec<String> },
 }
 
 impl Formula {
@@ -87,6 +97,7 @@ impl Formula {
 		}
 
 		Ok(Formula {
+			hypotheses: Vec::new(),
 			left: FormulaPart {
 				pattern: left,
 				unknown_patterns_names: left_unknown_patterns,
@@ -99,15 +110,64 @@ impl Formula {
 			},
 		})
 	}
+
+	/// Добавляет условия к формуле. Условия могут использовать только те паттерны и функции, которые есть в формуле, иначе при использовании формулы обязательства нельзя будет построить.
+	pub fn with_hypotheses(mut self, hypotheses: Vec<Hypothesis>) -> Result<Formula, FormulaError> {
+		let mut patterns = self.left.pattern.get_pattern_names();
+		patterns.extend(self.right.pattern.get_pattern_names());
+		let functions = self.left.pattern.get_anyfunction_names();
+
+		let mut unknown_patterns = BTreeSet::new();
+		let mut unknown_functions = BTreeSet::new();
+		for Hypothesis { left, right } in &hypotheses {
+			for expr in [left, right] {
+				unknown_patterns.extend(expr.get_pattern_names().difference(&patterns).cloned());
+				unknown_functions.extend(expr.get_anyfunction_names().difference(&functions).cloned());
+			}
+		}
+		if !unknown_patterns.is_empty() || !unknown_functions.is_empty() {
+			return Err(FormulaError::UnknownPatternsInHypotheses {
+				patterns: unknown_patterns.into_iter().collect(),
+				functions: unknown_functions.into_iter().collect(),
+			});
+		}
+
+		self.hypotheses = hypotheses;
+		Ok(self)
+	}
+
+	/// Запрещает паттерны `?x` в формуле и её условиях. Иначе формула с `?x` справа доказывалась бы метапеременной, которую проверка уточнила бы до чего угодно.
+	pub fn reject_metavariables(self) -> Result<Formula, FormulaError> {
+		let mut names = self.left.pattern.get_pattern_names();
+		names.extend(self.right.pattern.get_pattern_names());
+		for Hypothesis { left, right } in &self.hypotheses {
+			names.extend(left.get_pattern_names());
+			names.extend(right.get_pattern_names());
+		}
+		let names: Vec<String> = names.into_iter().filter(|name| is_metavariable(name)).collect();
+		if names.is_empty() { Ok(self) } else { Err(FormulaError::MetavariablesInFormula { names }) }
+	}
 }
 
-/// `left <-> right`
+/// `hypotheses |- left <-> right`
 #[derive(Clone, Debug)]
 pub struct Formula {
+	pub hypotheses: Vec<Hypothesis>,
 	pub left: FormulaPart,
 	pub right: FormulaPart,
 }
 
+/// Условие теоремы `left <-> right`. Его паттерны это паттерны самой теоремы, поэтому внутри доказательства оно применяется только к буквально такому же выражению, а при использовании теоремы превращается в обязательство.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hypothesis {
+	pub left: Expression,
+	pub right: Expression,
+}
+
+impl fmt::Display for Hypothesis {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{} <-> {}", self.left, self.right) }
+}
+
 /// `variable -> value`, позволяет производить замену с имени паттерна на выражение
 #[derive(Clone, Debug)]
 pub struct Binding {
@@ -122,25 +182,117 @@ impl Binding {
 #[derive(Default, Debug, Clone)]
 pub struct BindingStorage(BTreeMap<String, Expression>);
 
+/// Один паттерн должен принять два разных значения.
+#[derive(Debug, Error, Clone)]
+#[error("pattern `{pattern_name}` is bound to `{bound}`, but here it is `{actual}`")]
+pub struct BindingConflict {
+	pub pattern_name: String,
+	pub bound: Expression,
+	pub actual: Expression,
+}
+
+/// Почему выражение не подошло под паттерн. `position` указывает на подвыражение, в котором произошла ошибка, относительно корня сопоставляемого выражения.
+#[derive(Debug, Error, Clone)]
+#[error("{kind}")]
+pub struct MatchError {
+	pub position: ExprPositionOwned,
+	pub kind: MatchErrorKind,
+}
+
+#[derive(Debug, Error, Clone)]
+pub enum MatchErrorKind {
+	#[error("expected `{expected}`, but found `{actual}`")]
+	Mismatch { expected: Expression, actual: Expression },
+	#[error("{0}")]
+	Conflict(BindingConflict),
+	#[error("binding for function `${name}` is not provided")]
+	FunctionBindingNotFound { name: String },
+	#[error("function `${name}` is bound with {should_be} variables, but used with {actual} arguments")]
+	FunctionArityMismatch { name: String, should_be: usize, actual: usize },
+	#[error("variable `{variable}` of function `${name}` is not found in this expression")]
+	FunctionVariableNotFound { name: String, variable: String },
+}
+
+/// Почему не получилось подставить биндинги в `AnyFunction`.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum ApplyError {
+	#[error("binding for function `${name}` is not provided")]
+	FunctionBindingNotFound { name: String },
+	#[error("function `${name}` was never matched, so its inner bindings are unknown")]
+	FunctionNotMatched { name: String },
+	#[error("function `${name}` is bound with {should_be} variables, but used with {actual} arguments")]
+	FunctionArityMismatch { name: String, should_be: usize, actual: usize },
+}
+
+impl ApplyError {
+	/// Имя функции, для которой произошла ошибка.
+	pub fn function_name(&self) -> &str {
+		match self {
+			ApplyError::FunctionBindingNotFound { name }
+			| ApplyError::FunctionNotMatched { name }
+			| ApplyError::FunctionArityMismatch { name, .. } => name,
+		}
+	}
+}
+
+impl MatchError {
+	pub fn new(position: &ExprPositionOwned, kind: MatchErrorKind) -> Self {
+		MatchError { position: position.clone(), kind }
+	}
+}
+
 impl BindingStorage {
-	/// Добавляет биндинг в хранилище. Если он уже существует, то проверяет что они совпадают. Если это не так, возвращает None.
-	pub fn add(&mut self, binding: Binding) -> Option<()> {
+	/// Добавляет биндинг в хранилище. Если он уже существует, то проверяет что они совпадают. Если это не так, возвращает оба значения паттерна.
+	pub fn add(&mut self, binding: Binding) -> Result<(), BindingConflict> {
 		use std::collections::btree_map::Entry::*;
 
 		match self.0.entry(binding.pattern_name) {
 			Vacant(vacant) => {
 				vacant.insert(binding.to_value);
-				Some(())
+				Ok(())
 			},
 			Occupied(occupied) => {
 				if *occupied.get() == binding.to_value {
-					Some(())
+					Ok(())
 				} else {
-					None
+					Err(BindingConflict {
+						pattern_name: occupied.key().clone(),
+						bound: occupied.get().clone(),
+						actual: binding.to_value,
+					})
 				}
 			},
 		}
 	}
+
+	/// Записывает значение паттерна, заменяя предыдущее.
+	pub fn insert(&mut self, pattern_name: String, to_value: Expression) { self.0.insert(pattern_name, to_value); }
+
+	pub fn get(&self, pattern_name: &str) -> Option<&Expression> { self.0.get(pattern_name) }
+
+	pub fn iter(&self) -> impl Iterator<Item = (&String, &Expression)> { self.0.iter() }
+
+	pub fn len(&self) -> usize { self.0.len() }
+
+	pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+	/// Заменяет все связанные паттерны в выражении на их значения, включая паттерны внутри аргументов `AnyFunction`. Несвязанные паттерны остаются как есть.
+	pub fn substitute(&self, expr: &Expression) -> Expression {
+		use ExpressionMeta::*;
+
+		match &expr.0 {
+			Pattern { name } => self.0.get(name).cloned().unwrap_or_else(|| expr.clone()),
+			AnyFunction { name, args } => {
+				AnyFunction { name: name.clone(), args: args.iter().map(|arg| self.substitute(arg)).collect() }
+					.apply(Expression)
+			},
+			NamedFunction { name, args } => {
+				NamedFunction { name: name.clone(), args: args.iter().map(|arg| self.substitute(arg)).collect() }
+					.apply(Expression)
+			},
+			NamedValue { .. } | IntegerValue { .. } => expr.clone(),
+		}
+	}
 }
 
 pub trait AnyFunctionBinding {
@@ -149,15 +301,16 @@ pub trait AnyFunctionBinding {
 		any_function_name: &str,
 		args: &[Expression],
 		expr: Expression,
+		position: &mut ExprPositionOwned,
 		binding_storage: &mut BindingStorage,
-	) -> Option<()>;
+	) -> Result<(), MatchError>;
 
 	fn apply_bindings(
 		&self,
 		any_function_name: &str,
 		args: Vec<Expression>,
 		binding_storage: &BindingStorage,
-	) -> Option<Expression>;
+	) -> Result<Expression, ApplyError>;
 }
 
 pub fn find_bindings<A: AnyFunctionBinding>(
@@ -165,30 +318,52 @@ pub fn find_bindings<A: AnyFunctionBinding>(
 	by: &Expression,
 	binding_storage: &mut BindingStorage,
 	any_function_binding: &mut A,
-) -> Option<()> {
+) -> Result<(), MatchError> {
+	let mut position = Vec::new().apply(ExprPositionOwned);
+	find_bindings_at(expr, by, &mut position, binding_storage, any_function_binding)
+}
+
+/// То же, что `find_bindings`, но все позиции в ошибках отсчитываются от `position`.
+pub fn find_bindings_at<A: AnyFunctionBinding>(
+	expr: Expression,
+	by: &Expression,
+	position: &mut ExprPositionOwned,
+	binding_storage: &mut BindingStorage,
+	any_function_binding: &mut A,
+) -> Result<(), MatchError> {
 	use ExpressionMeta::*;
 
+	let mismatch = |position: &ExprPositionOwned, expr: Expression| {
+		MatchError::new(position, MatchErrorKind::Mismatch { expected: by.clone(), actual: expr })
+	};
+
 	match &by.0 {
-		Pattern { name } => binding_storage.add(Binding::new(name.to_string(), expr)),
-		AnyFunction { name, args } => any_function_binding.find_bindings(&name, &args, expr, binding_storage),
+		Pattern { name } => binding_storage
+			.add(Binding::new(name.to_string(), expr))
+			.map_err(|conflict| MatchError::new(position, MatchErrorKind::Conflict(conflict))),
+		AnyFunction { name, args } => {
+			any_function_binding.find_bindings(name, args, expr, position, binding_storage)
+		},
 		NamedFunction { name, args } => match expr.0 {
 			NamedFunction { name: name_expr, args: args_expr }
 				if *name == name_expr && args.len() == args_expr.len() =>
 			{
-				for (arg_expr, arg_by) in args_expr.into_iter().zip(args.iter()) {
-					find_bindings(arg_expr, &arg_by, binding_storage, any_function_binding)?;
+				for (index, (arg_expr, arg_by)) in args_expr.into_iter().zip(args.iter()).enumerate() {
+					position.0.push(index);
+					find_bindings_at(arg_expr, arg_by, position, binding_storage, any_function_binding)?;
+					position.0.pop().unwrap();
 				}
-				Some(())
+				Ok(())
 			}
-			_ => None,
+			expr => Err(mismatch(position, Expression(expr))),
 		},
 		NamedValue { name } => match expr.0 {
-			NamedValue { name: expr_name } if *name == expr_name => Some(()),
-			_ => None,
+			NamedValue { name: expr_name } if *name == expr_name => Ok(()),
+			expr => Err(mismatch(position, Expression(expr))),
 		},
 		IntegerValue { value } => match expr.0 {
-			IntegerValue { value: expr_value } if *value == expr_value => Some(()),
-			_ => None,
+			IntegerValue { value: expr_value } if *value == expr_value => Ok(()),
+			expr => Err(mismatch(position, Expression(expr))),
 		},
 	}
 }
@@ -197,10 +372,10 @@ pub fn apply_bindings<A: AnyFunctionBinding>(
 	expr: Expression,
 	binding_storage: &BindingStorage,
 	any_function_binding: &A,
-) -> Expression {
+) -> Result<Expression, ApplyError> {
 	use ExpressionMeta::*;
 
-	match expr.0 {
+	Ok(match expr.0 {
 		Pattern { name } => {
 			if let Some(found) = binding_storage.0.get(&name) {
 				found.clone()
@@ -208,20 +383,18 @@ pub fn apply_bindings<A: AnyFunctionBinding>(
 				Pattern { name }.apply(Expression)
 			}
 		},
-		AnyFunction { name, args } => any_function_binding
-			.apply_bindings(&name, args, binding_storage)
-			.unwrap(),
+		AnyFunction { name, args } => any_function_binding.apply_bindings(&name, args, binding_storage)?,
 		NamedFunction { name, args } => NamedFunction {
 			name,
 			args: args
 				.into_iter()
 				.map(|arg| apply_bindings(arg, binding_storage, any_function_binding))
-				.collect(),
+				.collect::<Result<_, _>>()?,
 		}
 		.apply(Expression),
 		NamedValue { name } => NamedValue { name }.apply(Expression),
 		IntegerValue { value } => IntegerValue { value }.apply(Expression),
-	}
+	})
 }
 
 /// `$f(..variables) := pattern`
@@ -231,6 +404,19 @@ pub struct AnyFunctionPattern {
 	pub variables: Vec<String>,
 }
 
+/// Позиция первого вхождения переменной `name` в паттерн функции. Внутрь `AnyFunction` не заходит, потому что там позиции паттерна и выражения не совпадают.
+fn variable_position(pattern: &Expression, name: &str) -> Option<Vec<usize>> {
+	match &pattern.0 {
+		ExpressionMeta::Pattern { name: pattern_name } if pattern_name == name => Some(Vec::new()),
+		ExpressionMeta::NamedFunction { args, .. } => args.iter().enumerate().find_map(|(index, arg)| {
+			let mut result = variable_position(arg, name)?;
+			result.insert(0, index);
+			Some(result)
+		}),
+		_ => None,
+	}
+}
+
 /// Позволяет матчиться с `AnyFunction` путём ручного задания паттерна который там должен находиться.
 pub struct ManualAnyFunctionBinding {
 	to_match: BTreeMap<String, AnyFunctionPattern>,
@@ -249,23 +435,39 @@ impl AnyFunctionBinding for ManualAnyFunctionBinding {
 		any_function_name: &str,
 		args: &[Expression],
 		expr: Expression,
+		position: &mut ExprPositionOwned,
 		global_bindings: &mut BindingStorage,
-	) -> Option<()> {
-		let AnyFunctionPattern { pattern, variables } = self.to_match.get(any_function_name)?.clone();
+	) -> Result<(), MatchError> {
+		let AnyFunctionPattern { pattern, variables } = self.to_match.get(any_function_name).cloned().ok_or_else(|| {
+			MatchError::new(position, MatchErrorKind::FunctionBindingNotFound { name: any_function_name.to_string() })
+		})?;
 		let mut local_bingings = BindingStorage::default();
-		crate::binding::find_bindings(expr, &pattern, &mut local_bingings, self)?;
+		crate::binding::find_bindings_at(expr, &pattern, position, &mut local_bingings, self)?;
 
 		if variables.len() != args.len() {
-			return None;
+			return Err(MatchError::new(position, MatchErrorKind::FunctionArityMismatch {
+				name: any_function_name.to_string(),
+				should_be: variables.len(),
+				actual: args.len(),
+			}));
 		}
 		for (name, arg) in variables.iter().zip(args.iter()) {
-			let binding = local_bingings.0.remove(name)?;
-			crate::binding::find_bindings(binding, &arg, global_bindings, self)?;
+			let binding = local_bingings.0.remove(name).ok_or_else(|| {
+				MatchError::new(position, MatchErrorKind::FunctionVariableNotFound {
+					name: any_function_name.to_string(),
+					variable: name.clone(),
+				})
+			})?;
+			// Ошибка внутри значения переменной указывает туда, где переменная стоит в выражении.
+			let depth = position.0.len();
+			position.0.extend(variable_position(&pattern, name).unwrap_or_default());
+			crate::binding::find_bindings_at(binding, arg, position, global_bindings, self)?;
+			position.0.truncate(depth);
 		}
 
 		self.bindings.insert(any_function_name.to_string(), local_bingings);
 
-		Some(())
+		Ok(())
 	}
 
 	fn apply_bindings(
@@ -273,14 +475,29 @@ impl AnyFunctionBinding for ManualAnyFunctionBinding {
 		any_function_name: &str,
 		args: Vec<Expression>,
 		global_bindings: &BindingStorage,
-	) -> Option<Expression> {
-		let AnyFunctionPattern { pattern, variables } = self.to_match.get(any_function_name)?.clone();
-		let mut local_bindings = self.bindings.get(any_function_name)?.clone();
+	) -> Result<Expression, ApplyError> {
+		let AnyFunctionPattern { pattern, variables } = self
+			.to_match
+			.get(any_function_name)
+			.ok_or_else(|| ApplyError::FunctionBindingNotFound { name: any_function_name.to_string() })?
+			.clone();
+		let mut local_bindings = self
+			.bindings
+			.get(any_function_name)
+			.ok_or_else(|| ApplyError::FunctionNotMatched { name: any_function_name.to_string() })?
+			.clone();
 
-		for (name, arg) in variables.into_iter().zip(args.into_iter()) {
-			local_bindings.add(Binding::new(name, apply_bindings(arg, global_bindings, self)));
+		if variables.len() != args.len() {
+			return Err(ApplyError::FunctionArityMismatch {
+				name: any_function_name.to_string(),
+				should_be: variables.len(),
+				actual: args.len(),
+			});
+		}
+		for (name, arg) in variables.into_iter().zip(args) {
+			local_bindings.insert(name, apply_bindings(arg, global_bindings, self)?);
 		}
 
-		Some(apply_bindings(pattern, &local_bindings, self))
+		apply_bindings(pattern, &local_bindings, self)
 	}
 }
@@ -0,0 +1,194 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	io,
+	path::Path,
+};
+
+use crate::{
+	binding::Formula,
+	infer::needs_inference,
+	parsing::{Math, NamedFormulas, ProofStep, Tactic},
+	proof::{check_proofs_parallel, FormulaPosition, ProofStepError},
+};
+
+const HEADER: &str = "fopply proof cache 1";
+
+/// FNV-1a. Используется вместо `DefaultHasher`, потому что хэши хранятся на диске и должны совпадать между версиями компилятора.
+#[derive(Clone, Copy)]
+struct StableHasher(u64);
+
+impl StableHasher {
+	fn new() -> Self { StableHasher(0xcbf2_9ce4_8422_2325) }
+
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= u64::from(*byte);
+			self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+		}
+	}
+
+	/// Строка с разделителем, чтобы `ab` + `c` и `a` + `bc` давали разные хэши.
+	fn write_str(&mut self, s: &str) {
+		self.write(s.as_bytes());
+		self.write(&[0xff]);
+	}
+
+	fn finish(self) -> u64 { self.0 }
+}
+
+fn formula_hash(formula: &Formula) -> u64 {
+	let mut hasher = StableHasher::new();
+	for hypothesis in &formula.hypotheses {
+		hasher.write_str(&hypothesis.to_string());
+	}
+	hasher.write_str(&formula.left.pattern.to_string());
+	hasher.write_str(&formula.right.pattern.to_string());
+	hasher.finish()
+}
+
+/// Кэш доказательств, которые уже были проверены и оказались верными.
+///
+/// Доказательство определяется хэшем своей формулы, текста доказательства и формул, которые в нём используются. Если ничего из этого не изменилось, то доказательство не проверяется повторно. Доказательства используемых формул в ключ не входят: они проверяются отдельно и на корректность текущего доказательства не влияют.
+#[derive(Clone, Debug, Default)]
+pub struct ProofCache {
+	verified: BTreeSet<u64>,
+	/// Сколько доказательств было проверено при последнем вызове `is_proofs_correct`, остальные взяты из кэша.
+	pub checked: usize,
+}
+
+impl ProofCache {
+	/// Читает кэш из файла. Если файла нет или он в другом формате, то возвращается пустой кэш.
+	pub fn load(path: &Path) -> Self {
+		let text = match std::fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(_) => return Self::default(),
+		};
+		let mut lines = text.lines();
+		if lines.next() != Some(HEADER) {
+			return Self::default();
+		}
+		let verified = lines.filter_map(|line| u64::from_str_radix(line, 16).ok()).collect();
+		ProofCache { verified, checked: 0 }
+	}
+
+	pub fn save(&self, path: &Path) -> io::Result<()> {
+		let mut text = String::from(HEADER);
+		for hash in &self.verified {
+			text += &format!("\n{:016x}", hash);
+		}
+		text.push('\n');
+		std::fs::write(path, text)
+	}
+
+	pub fn len(&self) -> usize { self.verified.len() }
+
+	pub fn is_empty(&self) -> bool { self.verified.is_empty() }
+
+	pub fn contains(&self, key: u64) -> bool { self.verified.contains(&key) }
+
+	/// Ключи доказательств `math`. Считаются до вывода шагов, потому что вывод дописывает в шаги формулы, а ключ должен совпадать с прошлым запуском.
+	///
+	/// Доказательство, в котором есть шаги без формулы, зависит от всего файла: формула для такого шага выбирается среди всех формул, а выбранная формула участвует в поиске циклов. Поэтому в его ключ входит весь `input`, и такие доказательства берутся из кэша, только если файл не менялся.
+	///
+	/// `input` это текст, из которого получена `math`, нужен для получения текста доказательств по их спанам.
+	pub fn keys(
+		input: &str,
+		math: &Math,
+		global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	) -> BTreeMap<FormulaPosition, u64> {
+		let formula_hashes: BTreeMap<&FormulaPosition, u64> =
+			global_formulas.iter().map(|(position, formula)| (position, formula_hash(formula))).collect();
+
+		let mut result = BTreeMap::new();
+		for NamedFormulas { name, formulas } in &math.0 {
+			for (index, formula) in formulas.iter().enumerate() {
+				let proof = match &formula.proof {
+					Some(proof) => proof,
+					None => continue,
+				};
+				let position = FormulaPosition { module_name: name.clone(), position: index + 1 };
+
+				let mut hasher = StableHasher::new();
+				match formula_hashes.get(&position) {
+					Some(hash) => hasher.write(&hash.to_le_bytes()),
+					None => hasher.write_str(&input[formula.formula.span.0.clone()]),
+				}
+				hasher.write_str(&input[proof.span.0.clone()]);
+				if needs_inference(formula) {
+					hasher.write_str(input);
+				}
+				let mut used: BTreeSet<FormulaPosition> = proof
+					.inner
+					.steps()
+					.flat_map(ProofStep::used_formulas)
+					.chain(proof.inner.tactics().flat_map(Tactic::used_formulas))
+					.map(|used_formula| FormulaPosition {
+						module_name: used_formula.inner.module_name.clone(),
+						position: used_formula.inner.position,
+					})
+					.collect();
+				for module in proof.inner.tactics().flat_map(Tactic::used_modules) {
+					used.extend(
+						global_formulas
+							.keys()
+							.filter(|used| used.module_name == module.inner && position.can_use_from_module(used))
+							.cloned(),
+					);
+				}
+				for used in &used {
+					hasher.write_str(&used.to_string());
+					match formula_hashes.get(used) {
+						Some(hash) => hasher.write(&hash.to_le_bytes()),
+						None => hasher.write_str("missing"),
+					}
+				}
+				result.insert(position, hasher.finish());
+			}
+		}
+		result
+	}
+
+	/// То же самое, что и `proof::is_proofs_correct`, только пропускает доказательства из кэша. После вызова в кэше остаются только доказательства из `math`, которые оказались верными.
+	///
+	/// `keys` посчитаны через `keys` до вывода шагов. Непроверенные доказательства проверяются в `threads` потоках.
+	pub fn is_proofs_correct(
+		&mut self,
+		keys: &BTreeMap<FormulaPosition, u64>,
+		math: &Math,
+		global_formulas: &BTreeMap<FormulaPosition, Formula>,
+		threads: usize,
+	) -> Result<(), Vec<ProofStepError>> {
+		let mut verified = BTreeSet::new();
+		let mut to_check = Vec::new();
+		for NamedFormulas { name, formulas } in &math.0 {
+			for (index, formula) in formulas.iter().enumerate() {
+				let proof = match &formula.proof {
+					Some(proof) => proof,
+					None => continue,
+				};
+				let position = FormulaPosition { module_name: name.clone(), position: index + 1 };
+				let key = keys[&position];
+				if self.verified.contains(&key) {
+					verified.insert(key);
+				} else {
+					to_check.push((key, (position, &formula.formula, proof)));
+				}
+			}
+		}
+
+		let (keys, proofs): (Vec<_>, Vec<_>) = to_check.into_iter().unzip();
+		let mut result = Vec::new();
+		for (key, checked) in keys.iter().zip(check_proofs_parallel(&proofs, global_formulas, threads)) {
+			match checked {
+				Ok(()) => {
+					verified.insert(*key);
+				},
+				Err(errors) => result.extend(errors),
+			}
+		}
+		self.checked = keys.len();
+		self.verified = verified;
+
+		if result.is_empty() { Ok(()) } else { Err(result) }
+	}
+}
@@ -0,0 +1,493 @@
+use std::{
+	cmp::Ordering,
+	collections::{BTreeMap, BTreeSet, VecDeque},
+	fmt,
+	time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::{
+	binding::{BindingStorage, Formula},
+	expr::*,
+	print::to_string,
+	proof::FormulaPosition,
+	unify::{rename_patterns, unify},
+	utils::joined_by::*,
+};
+
+/// Порядок на выражениях, по которому формулы ориентируются в правила переписывания. Он фундирован и согласован с подстановками и контекстами, поэтому переписывание правилами `left -> right`, где `left` больше `right`, всегда останавливается.
+///
+/// В обоих порядках функции сравниваются по `precedence`: имена в нём записаны от большего к меньшему, а имена, которых там нет, меньше всех записанных и сравниваются по числу аргументов, а затем по имени. `$true` и `$f` пишутся с `$`, числа как есть.
+#[derive(Clone, Debug)]
+pub enum TermOrder {
+	/// Лексикографический порядок путей.
+	Lpo { precedence: Vec<String> },
+	/// Порядок Кнута — Бендикса. Вес функции берётся из `weights`, по умолчанию он равен 1, как и вес паттерна. Веса должны быть положительными.
+	Kbo { precedence: Vec<String>, weights: BTreeMap<String, usize> },
+}
+
+/// Правило переписывания, все паттерны правой части есть в левой.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+	pub left: Expression,
+	pub right: Expression,
+}
+
+impl fmt::Display for Rule {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} -> {}", to_string(&self.left), to_string(&self.right))
+	}
+}
+
+/// Два результата, которые получаются из выражения `overlap`, если применить к нему два правила в пересекающихся местах.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CriticalPair {
+	pub overlap: Expression,
+	pub left: Expression,
+	pub right: Expression,
+}
+
+/// Когда прекращать пополнение.
+#[derive(Clone, Debug)]
+pub struct CompletionLimits {
+	/// Сколько правил может быть одновременно.
+	pub max_rules: usize,
+	/// Пополнение может не остановиться, а правила при этом растут медленнее, чем их левые части.
+	pub timeout: Option<Duration>,
+}
+
+impl Default for CompletionLimits {
+	fn default() -> Self { CompletionLimits { max_rules: 100, timeout: Some(Duration::from_secs(10)) } }
+}
+
+#[derive(Debug, Error)]
+pub enum CompletionError {
+	#[error(
+		"these equations can't be oriented: {}",
+		equations.iter().map(|(left, right)| format!("`{} <-> {}`", to_string(left), to_string(right))).joined_by(", ")
+	)]
+	Unorientable { rules: Vec<Rule>, equations: Vec<(Expression, Expression)> },
+	#[error("completion is stopped, there are more than {max_rules} rules")]
+	TooManyRules { max_rules: usize, rules: Vec<Rule> },
+	#[error("completion is stopped after {} seconds", timeout.as_secs_f64())]
+	Timeout { timeout: Duration, rules: Vec<Rule> },
+}
+
+/// Функция в корне выражения: имя и число аргументов. У паттерна её нет.
+fn symbol(expr: &Expression) -> Option<(String, &[Expression])> {
+	use ExpressionMeta::*;
+
+	match &expr.0 {
+		NamedFunction { name, args } => Some((name.clone(), args)),
+		AnyFunction { name, args } => Some((format!("${}", name), args)),
+		NamedValue { name } => Some((format!("${}", name), &[])),
+		IntegerValue { value } => Some((value.to_string(), &[])),
+		Pattern { .. } => None,
+	}
+}
+
+fn contains_pattern(expr: &Expression, pattern: &str) -> bool {
+	let mut result = false;
+	expr.travel(&mut |expr| result |= matches!(&expr.0, ExpressionMeta::Pattern { name } if name == pattern));
+	result
+}
+
+fn pattern_counts(expr: &Expression) -> BTreeMap<String, usize> {
+	let mut result = BTreeMap::new();
+	expr.travel(&mut |expr| {
+		if let ExpressionMeta::Pattern { name } = &expr.0 {
+			*result.entry(name.clone()).or_insert(0) += 1;
+		}
+	});
+	result
+}
+
+impl TermOrder {
+	fn precedence(&self) -> &[String] {
+		match self {
+			TermOrder::Lpo { precedence } | TermOrder::Kbo { precedence, .. } => precedence,
+		}
+	}
+
+	fn compare_symbols(&self, (a, a_args): (&str, usize), (b, b_args): (&str, usize)) -> Ordering {
+		let precedence = self.precedence();
+		let rank = |name: &str| precedence.iter().position(|x| x == name).map(|index| precedence.len() - index);
+		(rank(a), a_args, a).cmp(&(rank(b), b_args, b))
+	}
+
+	fn weight(&self, expr: &Expression) -> usize {
+		let weights = match self {
+			TermOrder::Kbo { weights, .. } => weights,
+			TermOrder::Lpo { .. } => return 0,
+		};
+		let mut result = 0;
+		expr.travel(&mut |expr| result += symbol(expr).and_then(|(name, _)| weights.get(&name).copied()).unwrap_or(1));
+		result
+	}
+
+	/// `left` строго больше `right`.
+	pub fn greater(&self, left: &Expression, right: &Expression) -> bool {
+		match self {
+			TermOrder::Lpo { .. } => self.lpo_greater(left, right),
+			TermOrder::Kbo { .. } => self.kbo_greater(left, right),
+		}
+	}
+
+	/// Первые различные аргументы сравниваются порядком, остальные не важны.
+	fn lexicographic_greater(&self, left: &[Expression], right: &[Expression]) -> bool {
+		match left.iter().zip(right).find(|(a, b)| a != b) {
+			Some((a, b)) => self.greater(a, b),
+			None => false,
+		}
+	}
+
+	fn lpo_greater(&self, left: &Expression, right: &Expression) -> bool {
+		if let ExpressionMeta::Pattern { name } = &right.0 {
+			return left != right && contains_pattern(left, name);
+		}
+		let ((f, left_args), (g, right_args)) = match (symbol(left), symbol(right)) {
+			(Some(f), Some(g)) => (f, g),
+			_ => return false,
+		};
+		if left_args.iter().any(|arg| arg == right || self.lpo_greater(arg, right)) {
+			return true;
+		}
+		let all_smaller = || right_args.iter().all(|arg| self.lpo_greater(left, arg));
+		match self.compare_symbols((&f, left_args.len()), (&g, right_args.len())) {
+			Ordering::Greater => all_smaller(),
+			Ordering::Equal => self.lexicographic_greater(left_args, right_args) && all_smaller(),
+			Ordering::Less => false,
+		}
+	}
+
+	fn kbo_greater(&self, left: &Expression, right: &Expression) -> bool {
+		let left_counts = pattern_counts(left);
+		if pattern_counts(right).iter().any(|(name, count)| left_counts.get(name).is_none_or(|x| x < count)) {
+			return false;
+		}
+		match self.weight(left).cmp(&self.weight(right)) {
+			Ordering::Greater => return true,
+			Ordering::Less => return false,
+			Ordering::Equal => {},
+		}
+		match (symbol(left), symbol(right)) {
+			(Some(_), None) => left != right,
+			(Some((f, left_args)), Some((g, right_args))) => {
+				match self.compare_symbols((&f, left_args.len()), (&g, right_args.len())) {
+					Ordering::Greater => true,
+					Ordering::Equal => self.lexicographic_greater(left_args, right_args),
+					Ordering::Less => false,
+				}
+			},
+			(None, _) => false,
+		}
+	}
+
+	/// Правило из равенства `a = b`, если одна из сторон больше другой.
+	pub fn orient(&self, a: Expression, b: Expression) -> Option<Rule> {
+		if self.greater(&a, &b) {
+			Some(Rule { left: a, right: b })
+		} else if self.greater(&b, &a) {
+			Some(Rule { left: b, right: a })
+		} else {
+			None
+		}
+	}
+}
+
+/// Сопоставляет паттерны `pattern` с `expr`. Паттерны `expr` считаются константами, а `$f` обычной функцией.
+fn match_pattern(pattern: &Expression, expr: &Expression, bindings: &mut BindingStorage) -> bool {
+	use ExpressionMeta::*;
+
+	match (&pattern.0, &expr.0) {
+		(Pattern { name }, _) => match bindings.get(name) {
+			Some(bound) => bound == expr,
+			None => {
+				bindings.insert(name.clone(), expr.clone());
+				true
+			},
+		},
+		(AnyFunction { name: a, args: args_a }, AnyFunction { name: b, args: args_b })
+		| (NamedFunction { name: a, args: args_a }, NamedFunction { name: b, args: args_b }) => {
+			a == b
+				&& args_a.len() == args_b.len()
+				&& args_a.iter().zip(args_b).all(|(a, b)| match_pattern(a, b, bindings))
+		},
+		_ => pattern == expr,
+	}
+}
+
+fn try_map_args(expr: &Expression, f: impl FnMut(&Expression) -> Option<Expression>) -> Option<Expression> {
+	use ExpressionMeta::*;
+
+	Some(Expression(match &expr.0 {
+		AnyFunction { name, args } => {
+			AnyFunction { name: name.clone(), args: args.iter().map(f).collect::<Option<_>>()? }
+		},
+		NamedFunction { name, args } => {
+			NamedFunction { name: name.clone(), args: args.iter().map(f).collect::<Option<_>>()? }
+		},
+		other => other.clone(),
+	}))
+}
+
+/// Результат применения первого подходящего правила к корню выражения.
+fn rewrite_root(expr: &Expression, rules: &[Rule]) -> Option<Expression> {
+	rules.iter().find_map(|rule| {
+		let mut bindings = BindingStorage::default();
+		match_pattern(&rule.left, expr, &mut bindings).then(|| bindings.substitute(&rule.right))
+	})
+}
+
+/// Переписывает выражение правилами, пока это возможно, начиная с аргументов. Правила должны быть ориентированы одним порядком, иначе переписывание может не остановиться.
+pub fn normalize(expr: &Expression, rules: &[Rule]) -> Expression {
+	let mut steps = usize::MAX;
+	normalize_limited(expr, rules, &mut steps).expect("steps can't run out")
+}
+
+/// То же, что `normalize`, но делает не больше `steps` переписываний. Если их не хватило, возвращает `None`.
+pub fn normalize_limited(expr: &Expression, rules: &[Rule], steps: &mut usize) -> Option<Expression> {
+	let mut expr = try_map_args(expr, |arg| normalize_limited(arg, rules, steps))?;
+	while let Some(result) = rewrite_root(&expr, rules) {
+		*steps = steps.checked_sub(1)?;
+		expr = try_map_args(&result, |arg| normalize_limited(arg, rules, steps))?;
+	}
+	Some(expr)
+}
+
+/// Все выражения, в которые `expr` переписывается за один шаг любым правилом в любом месте.
+fn reducts(expr: &Expression, rules: &[Rule]) -> Vec<Expression> {
+	use ExpressionMeta::*;
+
+	let mut result: Vec<Expression> = rules
+		.iter()
+		.filter_map(|rule| {
+			let mut bindings = BindingStorage::default();
+			match_pattern(&rule.left, expr, &mut bindings).then(|| bindings.substitute(&rule.right))
+		})
+		.collect();
+	if let AnyFunction { args, .. } | NamedFunction { args, .. } = &expr.0 {
+		for (index, arg) in args.iter().enumerate() {
+			for reduct in reducts(arg, rules) {
+				let mut changed = expr.clone();
+				if let AnyFunction { args, .. } | NamedFunction { args, .. } = &mut changed.0 {
+					args[index] = reduct;
+				}
+				result.push(changed);
+			}
+		}
+	}
+	result
+}
+
+/// Переписываются ли `left` и `right` в одно и то же выражение. Обе стороны переписываются в ширину всеми правилами во всех местах, пока множества полученных выражений не пересекутся или не будет получено `steps` выражений. В отличие от сравнения нормальных форм, работает и с правилами, переписывание которыми не останавливается.
+pub fn joinable_within(left: &Expression, right: &Expression, rules: &[Rule], steps: usize) -> bool {
+	if left == right {
+		return true;
+	}
+	let mut seen = [BTreeSet::new(), BTreeSet::new()];
+	let mut queues = [VecDeque::new(), VecDeque::new()];
+	for (side, expr) in [left, right].iter().enumerate() {
+		seen[side].insert((*expr).clone());
+		queues[side].push_back((*expr).clone());
+	}
+
+	let mut made = 0;
+	while !queues[0].is_empty() || !queues[1].is_empty() {
+		// Раскрывается сторона, у которой меньше очередь, но не пустая.
+		let side = match (queues[0].len(), queues[1].len()) {
+			(0, _) => 1,
+			(left, right) if right == 0 || left <= right => 0,
+			_ => 1,
+		};
+		let expr = queues[side].pop_front().unwrap();
+		for reduct in reducts(&expr, rules) {
+			if seen[1 - side].contains(&reduct) {
+				return true;
+			}
+			made += 1;
+			if made > steps {
+				return false;
+			}
+			if seen[side].insert(reduct.clone()) {
+				queues[side].push_back(reduct);
+			}
+		}
+	}
+	false
+}
+
+/// Какое-нибудь правило применимо к какому-нибудь подвыражению.
+pub fn is_reducible(expr: &Expression, rules: &[Rule]) -> bool {
+	let mut result = false;
+	expr.travel(&mut |part| result = result || rewrite_root(part, rules).is_some());
+	result
+}
+
+/// Переименовывает паттерны выражений в `a`, `b`, `c`, ... в порядке появления, чтобы одинаковые с точностью до имён паттернов выражения совпадали.
+pub fn rename_canonical(exprs: &mut [&mut Expression]) {
+	let mut names = BTreeMap::new();
+	for expr in exprs.iter() {
+		expr.travel(&mut |expr| {
+			if let ExpressionMeta::Pattern { name } = &expr.0 {
+				let index = names.len();
+				names.entry(name.clone()).or_insert_with(|| {
+					let letter = (b'a' + (index % 26) as u8) as char;
+					if index < 26 { letter.to_string() } else { format!("{}{}", letter, index / 26) }
+				});
+			}
+		});
+	}
+	for expr in exprs.iter_mut() {
+		expr.travel_mut(&mut |expr| {
+			if let ExpressionMeta::Pattern { name } = &mut expr.0 {
+				*name = names[name.as_str()].clone();
+			}
+		});
+	}
+}
+
+/// Критические пары двух правил: левая часть `second` унифицируется с каждым подвыражением левой части `first`, которое не является паттерном. Если правило одно и то же, корень пропускается.
+pub fn critical_pairs(first: &Rule, second: &Rule, same: bool) -> Vec<CriticalPair> {
+	// Паттерны правил разделяются суффиксами, которые не могут встретиться в записанных именах.
+	let (left1, right1) = (rename_patterns(&first.left, "'"), rename_patterns(&first.right, "'"));
+	let (left2, right2) = (rename_patterns(&second.left, "''"), rename_patterns(&second.right, "''"));
+
+	let mut positions = Vec::new();
+	left1.travel_positions(|part, position| {
+		// Корень правила с самим собой даёт одинаковые результаты.
+		let trivial = same && position.0.is_empty();
+		if !(matches!(part.0, ExpressionMeta::Pattern { .. }) || trivial) {
+			positions.push(ExprPositionOwned(position.0.to_vec()));
+		}
+	});
+
+	let mut result = Vec::new();
+	for position in positions {
+		let substitution = match unify(left1.get(&position).unwrap(), &left2) {
+			Ok(x) => x,
+			Err(_) => continue,
+		};
+		let mut replaced = left1.clone();
+		*replaced.get_mut(&position).unwrap() = right2.clone();
+		let mut overlap = substitution.substitute(&left1);
+		let mut left = substitution.substitute(&right1);
+		let mut right = substitution.substitute(&replaced);
+		rename_canonical(&mut [&mut overlap, &mut left, &mut right]);
+		result.push(CriticalPair { overlap, left, right });
+	}
+	result
+}
+
+/// Равенства из формул модулей `modules`, которые можно превратить в правила. Формулы с условиями и с `$f` не подходят: первые верны не всегда, а `$f` означает любую функцию. Они возвращаются вторым значением.
+pub fn module_equations(
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	modules: &[String],
+) -> (Vec<(FormulaPosition, Expression, Expression)>, Vec<FormulaPosition>) {
+	let mut equations = Vec::new();
+	let mut skipped = Vec::new();
+	for (position, formula) in global_formulas.iter().filter(|(position, _)| modules.contains(&position.module_name)) {
+		let (left, right) = (&formula.left.pattern, &formula.right.pattern);
+		if formula.hypotheses.is_empty()
+			&& left.get_anyfunction_names().is_empty()
+			&& right.get_anyfunction_names().is_empty()
+		{
+			equations.push((position.clone(), left.clone(), right.clone()));
+		} else {
+			skipped.push(position.clone());
+		}
+	}
+	(equations, skipped)
+}
+
+/// Пополнение по Кнуту — Бендиксу. Равенства ориентируются порядком `order`, критические пары новых правил добавляются в равенства, а правила, левая часть которых переписывается новым правилом, снова становятся равенствами. Возвращает конфлюэнтную и завершающуюся систему правил, в которой левые части не переписываются другими правилами, а правые приведены к нормальной форме.
+///
+/// Равенства, которые нельзя ориентировать, откладываются и пробуются снова, когда правила меняются. Если они так и не сводятся, возвращаются вместе с найденными правилами.
+pub fn complete(
+	equations: Vec<(Expression, Expression)>,
+	order: &TermOrder,
+	limits: &CompletionLimits,
+) -> Result<Vec<Rule>, CompletionError> {
+	let mut rules: Vec<Rule> = Vec::new();
+	let mut pending: VecDeque<(Expression, Expression)> = equations.into();
+	let mut postponed = Vec::new();
+	let start = Instant::now();
+	loop {
+		while let Some((a, b)) = pending.pop_front() {
+			if let Some(timeout) = limits.timeout.filter(|timeout| start.elapsed() > *timeout) {
+				return Err(CompletionError::Timeout { timeout, rules });
+			}
+			let (a, b) = (normalize(&a, &rules), normalize(&b, &rules));
+			if a == b {
+				continue;
+			}
+			let mut rule = match order.orient(a.clone(), b.clone()) {
+				Some(rule) => rule,
+				None => {
+					postponed.push((a, b));
+					continue;
+				},
+			};
+			rename_canonical(&mut [&mut rule.left, &mut rule.right]);
+
+			let new_rule = std::slice::from_ref(&rule);
+			let mut kept = Vec::new();
+			for old in rules.drain(..) {
+				if is_reducible(&old.left, new_rule) {
+					pending.push_back((old.left, old.right));
+				} else {
+					kept.push(old);
+				}
+			}
+			kept.push(rule.clone());
+			let snapshot = kept.clone();
+			for old in &mut kept {
+				old.right = normalize(&old.right, &snapshot);
+			}
+			rules = kept;
+			if rules.len() > limits.max_rules {
+				return Err(CompletionError::TooManyRules { max_rules: limits.max_rules, rules });
+			}
+
+			let rule = rules.last().unwrap();
+			for other in &rules {
+				let same = other == rule;
+				let pairs = critical_pairs(rule, other, same).into_iter().chain(if same {
+					Vec::new()
+				} else {
+					critical_pairs(other, rule, false)
+				});
+				pending.extend(pairs.map(|pair| (pair.left, pair.right)));
+			}
+		}
+
+		// Отложенные равенства могли свестись или стать ориентируемыми после новых правил.
+		let mut still = Vec::new();
+		for (a, b) in postponed.drain(..) {
+			let (a, b) = (normalize(&a, &rules), normalize(&b, &rules));
+			if a == b {
+				continue;
+			}
+			if order.orient(a.clone(), b.clone()).is_some() {
+				pending.push_back((a, b));
+			} else {
+				still.push((a, b));
+			}
+		}
+		if pending.is_empty() {
+			if still.is_empty() {
+				return Ok(rules);
+			}
+			let mut equations = Vec::new();
+			for (mut a, mut b) in still {
+				rename_canonical(&mut [&mut a, &mut b]);
+				if !equations.contains(&(a.clone(), b.clone())) && !equations.contains(&(b.clone(), a.clone())) {
+					equations.push((a, b));
+				}
+			}
+			return Err(CompletionError::Unorientable { rules, equations });
+		}
+		postponed = still;
+	}
+}
@@ -0,0 +1,396 @@
+use std::{
+	collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+	hash::{Hash, Hasher},
+};
+
+use crate::{
+	binding::{AnyFunctionPattern, ApplyError, BindingConflict, MatchError, MatchErrorKind},
+	expr::*,
+	utils::apply::*,
+};
+
+/// Интернированное имя: паттерна, функции или константы.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Symbol(u32);
+
+/// Ссылка на узел в `ExprStore`. Узлы хешконсятся, поэтому два `NodeId` из одного хранилища равны тогда и только тогда, когда равны выражения.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+	/// Номер узла в хранилище, узлы нумеруются подряд с нуля в порядке добавления.
+	pub fn index(self) -> usize { self.0 as usize }
+}
+
+/// Аналог `ExpressionMeta`, но с интернированными именами и общими поддеревьями.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Node {
+	Pattern { name: Symbol },
+	AnyFunction { name: Symbol, args: Box<[NodeId]> },
+	NamedFunction { name: Symbol, args: Box<[NodeId]> },
+	NamedValue { name: Symbol },
+	IntegerValue { value: i64 },
+}
+
+impl Node {
+	pub fn args(&self) -> &[NodeId] {
+		match self {
+			Node::AnyFunction { args, .. } | Node::NamedFunction { args, .. } => args,
+			Node::Pattern { .. } | Node::NamedValue { .. } | Node::IntegerValue { .. } => &[],
+		}
+	}
+
+	pub fn kind(&self) -> ExpressionKind {
+		match self {
+			Node::Pattern { .. } => ExpressionKind::Pattern,
+			Node::AnyFunction { .. } => ExpressionKind::AnyFunction,
+			Node::NamedFunction { .. } => ExpressionKind::NamedFunction,
+			Node::NamedValue { .. } => ExpressionKind::NamedValue,
+			Node::IntegerValue { .. } => ExpressionKind::IntegerValue,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+struct NodeData {
+	node: Node,
+	hash: u64,
+	size: usize,
+	ground: bool,
+}
+
+/// Хранилище выражений в виде DAG. Имена интернируются, одинаковые поддеревья хранятся один раз, для каждого узла запоминается структурный хеш, который не зависит от порядка добавления и совпадает между разными хранилищами.
+///
+/// Это представление для e-графа из `egraph`, где одно выражение входит во многие классы. Проверку доказательств оно не ускоряет: проверка, `prove` и вывод шагов работают с `Expression`, а перевод выражения в хранилище на каждом шаге стоит столько же, сколько сопоставление с формулой. `find_bindings` и `apply_bindings` здесь нужны тем, кто уже держит выражения в хранилище.
+#[derive(Default, Clone, Debug)]
+pub struct ExprStore {
+	symbols: HashMap<String, Symbol>,
+	names: Vec<String>,
+	nodes: Vec<NodeData>,
+	lookup: HashMap<Node, NodeId>,
+}
+
+/// `паттерн -> узел`, аналог `BindingStorage` для `ExprStore`.
+#[derive(Default, Clone, Debug, Eq, PartialEq)]
+pub struct NodeBindings(pub BTreeMap<Symbol, NodeId>);
+
+/// `$f(..variables) := pattern` для `ExprStore`.
+#[derive(Clone, Debug)]
+pub struct NodeFunctionPattern {
+	pub pattern: NodeId,
+	pub variables: Vec<Symbol>,
+}
+
+/// Аналог `ManualAnyFunctionBinding`: функции задаются вручную, а при сопоставлении запоминаются биндинги паттерна функции.
+#[derive(Default, Clone, Debug)]
+pub struct NodeFunctionBindings {
+	pub to_match: BTreeMap<Symbol, NodeFunctionPattern>,
+	bindings: BTreeMap<Symbol, NodeBindings>,
+}
+
+impl NodeFunctionBindings {
+	pub fn new(to_match: BTreeMap<Symbol, NodeFunctionPattern>) -> Self {
+		NodeFunctionBindings { to_match, bindings: BTreeMap::new() }
+	}
+}
+
+impl ExprStore {
+	pub fn symbol(&mut self, name: &str) -> Symbol {
+		if let Some(symbol) = self.symbols.get(name) {
+			return *symbol;
+		}
+		let symbol = Symbol(self.names.len() as u32);
+		self.names.push(name.to_string());
+		self.symbols.insert(name.to_string(), symbol);
+		symbol
+	}
+
+	pub fn find_symbol(&self, name: &str) -> Option<Symbol> { self.symbols.get(name).copied() }
+
+	pub fn name(&self, symbol: Symbol) -> &str { &self.names[symbol.0 as usize] }
+
+	pub fn node(&self, id: NodeId) -> &Node { &self.nodes[id.0 as usize].node }
+
+	/// Структурный хеш выражения.
+	pub fn hash(&self, id: NodeId) -> u64 { self.nodes[id.0 as usize].hash }
+
+	/// Число узлов в выражении, если развернуть его в дерево.
+	pub fn size(&self, id: NodeId) -> usize { self.nodes[id.0 as usize].size }
+
+	/// В выражении нет ни паттернов, ни `AnyFunction`.
+	pub fn is_ground(&self, id: NodeId) -> bool { self.nodes[id.0 as usize].ground }
+
+	/// Количество различных узлов в хранилище.
+	pub fn len(&self) -> usize { self.nodes.len() }
+
+	pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+	/// Все узлы в порядке добавления.
+	pub fn ids(&self) -> impl Iterator<Item = NodeId> { (0..self.nodes.len() as u32).map(NodeId) }
+
+	/// Добавляет узел, если такого ещё нет, и возвращает его идентификатор.
+	pub fn add(&mut self, node: Node) -> NodeId {
+		if let Some(id) = self.lookup.get(&node) {
+			return *id;
+		}
+
+		let mut hasher = DefaultHasher::new();
+		self.node_kind_hash(&node, &mut hasher);
+		for arg in node.args() {
+			self.hash(*arg).hash(&mut hasher);
+		}
+		let hash = hasher.finish();
+		let size = 1 + node.args().iter().map(|arg| self.size(*arg)).sum::<usize>();
+		let ground = match &node {
+			Node::Pattern { .. } | Node::AnyFunction { .. } => false,
+			Node::NamedFunction { args, .. } => args.iter().all(|arg| self.is_ground(*arg)),
+			Node::NamedValue { .. } | Node::IntegerValue { .. } => true,
+		};
+
+		let id = NodeId(self.nodes.len() as u32);
+		self.nodes.push(NodeData { node: node.clone(), hash, size, ground });
+		self.lookup.insert(node, id);
+		id
+	}
+
+	/// Хеширует всё, кроме аргументов, через имена, а не через `Symbol`, чтобы хеш не зависел от хранилища.
+	fn node_kind_hash(&self, node: &Node, hasher: &mut DefaultHasher) {
+		node.kind().hash(hasher);
+		match node {
+			Node::Pattern { name } | Node::NamedValue { name } => self.name(*name).hash(hasher),
+			Node::AnyFunction { name, args } | Node::NamedFunction { name, args } => {
+				self.name(*name).hash(hasher);
+				args.len().hash(hasher);
+			},
+			Node::IntegerValue { value } => value.hash(hasher),
+		}
+	}
+
+	/// Переводит `Expression` в DAG.
+	pub fn intern(&mut self, expr: &Expression) -> NodeId {
+		use ExpressionMeta::*;
+
+		let node = match &expr.0 {
+			Pattern { name } => Node::Pattern { name: self.symbol(name) },
+			AnyFunction { name, args } => Node::AnyFunction {
+				name: self.symbol(name),
+				args: args.iter().map(|arg| self.intern(arg)).collect(),
+			},
+			NamedFunction { name, args } => Node::NamedFunction {
+				name: self.symbol(name),
+				args: args.iter().map(|arg| self.intern(arg)).collect(),
+			},
+			NamedValue { name } => Node::NamedValue { name: self.symbol(name) },
+			IntegerValue { value } => Node::IntegerValue { value: *value },
+		};
+		self.add(node)
+	}
+
+	/// Переводит узел обратно в `Expression`.
+	pub fn to_expression(&self, id: NodeId) -> Expression {
+		use ExpressionMeta::*;
+
+		match self.node(id) {
+			Node::Pattern { name } => Pattern { name: self.name(*name).to_string() },
+			Node::AnyFunction { name, args } => AnyFunction {
+				name: self.name(*name).to_string(),
+				args: args.iter().map(|arg| self.to_expression(*arg)).collect(),
+			},
+			Node::NamedFunction { name, args } => NamedFunction {
+				name: self.name(*name).to_string(),
+				args: args.iter().map(|arg| self.to_expression(*arg)).collect(),
+			},
+			Node::NamedValue { name } => NamedValue { name: self.name(*name).to_string() },
+			Node::IntegerValue { value } => IntegerValue { value: *value },
+		}
+		.apply(Expression)
+	}
+
+	pub fn intern_function_pattern(&mut self, pattern: &AnyFunctionPattern) -> NodeFunctionPattern {
+		NodeFunctionPattern {
+			pattern: self.intern(&pattern.pattern),
+			variables: pattern.variables.iter().map(|name| self.symbol(name)).collect(),
+		}
+	}
+
+	/// Получить узел внутри выражения по позиции.
+	pub fn get(&self, id: NodeId, position: &ExprPosition) -> Option<NodeId> {
+		position.0.iter().try_fold(id, |id, index| self.node(id).args().get(*index).copied())
+	}
+
+	/// Заменяет узел по позиции на `to`, переиспользуя все остальные поддеревья.
+	pub fn replace(&mut self, id: NodeId, position: &ExprPosition, to: NodeId) -> Option<NodeId> {
+		match &position.0 {
+			[] => Some(to),
+			[start, tail @ ..] => {
+				let mut node = self.node(id).clone();
+				let args = match &mut node {
+					Node::AnyFunction { args, .. } | Node::NamedFunction { args, .. } => args,
+					Node::Pattern { .. } | Node::NamedValue { .. } | Node::IntegerValue { .. } => return None,
+				};
+				let arg = args.get_mut(*start)?;
+				*arg = self.replace(*arg, ExprPosition::from_slice(tail), to)?;
+				Some(self.add(node))
+			},
+		}
+	}
+
+	/// Аналог `binding::find_bindings`: сопоставляет выражение `expr` с паттерном `by`. Поддеревья и значения паттернов сравниваются по `NodeId`.
+	pub fn find_bindings(
+		&self,
+		expr: NodeId,
+		by: NodeId,
+		bindings: &mut NodeBindings,
+		functions: &mut NodeFunctionBindings,
+	) -> Result<(), MatchError> {
+		let mut position = Vec::new().apply(ExprPositionOwned);
+		self.find_bindings_at(expr, by, &mut position, bindings, functions)
+	}
+
+	fn find_bindings_at(
+		&self,
+		expr: NodeId,
+		by: NodeId,
+		position: &mut ExprPositionOwned,
+		bindings: &mut NodeBindings,
+		functions: &mut NodeFunctionBindings,
+	) -> Result<(), MatchError> {
+		let mismatch = |position: &ExprPositionOwned| {
+			MatchError::new(position, MatchErrorKind::Mismatch {
+				expected: self.to_expression(by),
+				actual: self.to_expression(expr),
+			})
+		};
+
+		// Паттерн без переменных совпадает только с самим собой.
+		if self.is_ground(by) {
+			return if expr == by { Ok(()) } else { Err(mismatch(position)) };
+		}
+
+		match self.node(by) {
+			Node::Pattern { name } => self.bind(*name, expr, bindings).map_err(|kind| MatchError::new(position, kind)),
+			Node::AnyFunction { name, args } => self.find_function_bindings(*name, args, expr, position, bindings, functions),
+			Node::NamedFunction { name, args } => match self.node(expr) {
+				Node::NamedFunction { name: name_expr, args: args_expr } if name == name_expr && args.len() == args_expr.len() => {
+					for (index, (arg_expr, arg_by)) in args_expr.iter().zip(args.iter()).enumerate() {
+						position.0.push(index);
+						self.find_bindings_at(*arg_expr, *arg_by, position, bindings, functions)?;
+						position.0.pop().unwrap();
+					}
+					Ok(())
+				},
+				_ => Err(mismatch(position)),
+			},
+			Node::NamedValue { .. } | Node::IntegerValue { .. } => unreachable!(),
+		}
+	}
+
+	fn find_function_bindings(
+		&self,
+		name: Symbol,
+		args: &[NodeId],
+		expr: NodeId,
+		position: &mut ExprPositionOwned,
+		bindings: &mut NodeBindings,
+		functions: &mut NodeFunctionBindings,
+	) -> Result<(), MatchError> {
+		let function_name = || self.name(name).to_string();
+		let NodeFunctionPattern { pattern, variables } = functions.to_match.get(&name).cloned().ok_or_else(|| {
+			MatchError::new(position, MatchErrorKind::FunctionBindingNotFound { name: function_name() })
+		})?;
+
+		let mut local_bindings = NodeBindings::default();
+		self.find_bindings_at(expr, pattern, position, &mut local_bindings, functions)?;
+
+		if variables.len() != args.len() {
+			return Err(MatchError::new(position, MatchErrorKind::FunctionArityMismatch {
+				name: function_name(),
+				should_be: variables.len(),
+				actual: args.len(),
+			}));
+		}
+		for (variable, arg) in variables.iter().zip(args.iter()) {
+			let binding = local_bindings.0.remove(variable).ok_or_else(|| {
+				MatchError::new(position, MatchErrorKind::FunctionVariableNotFound {
+					name: function_name(),
+					variable: self.name(*variable).to_string(),
+				})
+			})?;
+			self.find_bindings_at(binding, *arg, position, bindings, functions)?;
+		}
+
+		functions.bindings.insert(name, local_bindings);
+		Ok(())
+	}
+
+	fn bind(&self, name: Symbol, value: NodeId, bindings: &mut NodeBindings) -> Result<(), MatchErrorKind> {
+		use std::collections::btree_map::Entry::*;
+
+		match bindings.0.entry(name) {
+			Vacant(vacant) => {
+				vacant.insert(value);
+				Ok(())
+			},
+			Occupied(occupied) => {
+				if *occupied.get() == value {
+					Ok(())
+				} else {
+					Err(MatchErrorKind::Conflict(BindingConflict {
+						pattern_name: self.name(name).to_string(),
+						bound: self.to_expression(*occupied.get()),
+						actual: self.to_expression(value),
+					}))
+				}
+			},
+		}
+	}
+
+	/// Аналог `binding::apply_bindings`. Поддеревья без паттернов возвращаются как есть, без копирования.
+	pub fn apply_bindings(
+		&mut self,
+		id: NodeId,
+		bindings: &NodeBindings,
+		functions: &NodeFunctionBindings,
+	) -> Result<NodeId, ApplyError> {
+		if self.is_ground(id) {
+			return Ok(id);
+		}
+
+		match self.node(id).clone() {
+			Node::Pattern { name } => Ok(bindings.0.get(&name).copied().unwrap_or(id)),
+			Node::AnyFunction { name, args } => {
+				let function_name = || self.name(name).to_string();
+				let NodeFunctionPattern { pattern, variables } = functions
+					.to_match
+					.get(&name)
+					.ok_or_else(|| ApplyError::FunctionBindingNotFound { name: function_name() })?
+					.clone();
+				let mut local_bindings = functions
+					.bindings
+					.get(&name)
+					.ok_or_else(|| ApplyError::FunctionNotMatched { name: function_name() })?
+					.clone();
+				if variables.len() != args.len() {
+					return Err(ApplyError::FunctionArityMismatch {
+						name: function_name(),
+						should_be: variables.len(),
+						actual: args.len(),
+					});
+				}
+				for (variable, arg) in variables.into_iter().zip(args.iter()) {
+					let arg = self.apply_bindings(*arg, bindings, functions)?;
+					local_bindings.0.insert(variable, arg);
+				}
+				self.apply_bindings(pattern, &local_bindings, functions)
+			},
+			Node::NamedFunction { name, args } => {
+				let args = args
+					.iter()
+					.map(|arg| self.apply_bindings(*arg, bindings, functions))
+					.collect::<Result<Box<[NodeId]>, _>>()?;
+				Ok(self.add(Node::NamedFunction { name, args }))
+			},
+			Node::NamedValue { .. } | Node::IntegerValue { .. } => unreachable!(),
+		}
+	}
+}
@@ -0,0 +1,358 @@
+use std::{
+	collections::{BTreeMap, HashMap, HashSet, VecDeque},
+	time::Instant,
+};
+
+use crate::{
+	binding::{find_bindings, Binding, BindingStorage, Formula, ManualAnyFunctionBinding},
+	dag::{ExprStore, Node, NodeId},
+	expr::*,
+	index::IndexedRule,
+	parsing::{Math, HYPOTHESIS_MODULE},
+	print::PlainStep,
+	proof::FormulaPosition,
+	search::{allowed_formulas, to_parsing, SearchError, SearchLimits},
+};
+
+/// Почему два узла оказались в одном классе.
+#[derive(Clone, Debug)]
+enum Justification {
+	/// Второй узел получен из первого применением формулы к корню.
+	Rule(IndexedRule),
+	/// У узлов одна функция и попарно равные аргументы.
+	Congruence,
+}
+
+impl Justification {
+	fn reversed(&self) -> Self {
+		match self {
+			Justification::Rule(rule) => {
+				Justification::Rule(IndexedRule { left_to_right: !rule.left_to_right, ..rule.clone() })
+			},
+			Justification::Congruence => Justification::Congruence,
+		}
+	}
+}
+
+/// `паттерн формулы -> класс`.
+type EBindings = BTreeMap<String, NodeId>;
+
+/// E-граф над конкретными выражениями из `ExprStore`. Каждый класс это множество равных выражений.
+///
+/// Для каждого объединения запоминается, почему оно произошло, и эти рёбра образуют лес. Путь в лесу между двумя узлами одного класса разворачивается в обычные шаги доказательства.
+#[derive(Default)]
+struct EGraph {
+	store: ExprStore,
+	parent: Vec<NodeId>,
+	/// Узлы класса, заполнено только для корней.
+	members: Vec<Vec<NodeId>>,
+	/// Рёбра объединений: `edges[a]` содержит `(b, justification)`, если `b` получается из `a` по `justification`.
+	edges: Vec<Vec<(NodeId, Justification)>>,
+}
+
+impl EGraph {
+	/// Заводит классы для узлов, которые появились в хранилище.
+	fn sync(&mut self) {
+		for id in self.store.ids().skip(self.parent.len()) {
+			self.parent.push(id);
+			self.members.push(vec![id]);
+			self.edges.push(Vec::new());
+		}
+	}
+
+	fn add(&mut self, expr: &Expression) -> NodeId {
+		let result = self.store.intern(expr);
+		self.sync();
+		result
+	}
+
+	fn find(&self, mut id: NodeId) -> NodeId {
+		while self.parent[id.index()] != id {
+			id = self.parent[id.index()];
+		}
+		id
+	}
+
+	fn union(&mut self, a: NodeId, b: NodeId, justification: Justification) -> bool {
+		let (mut root_a, mut root_b) = (self.find(a), self.find(b));
+		if root_a == root_b {
+			return false;
+		}
+		self.edges[b.index()].push((a, justification.reversed()));
+		self.edges[a.index()].push((b, justification));
+		if self.members[root_a.index()].len() < self.members[root_b.index()].len() {
+			std::mem::swap(&mut root_a, &mut root_b);
+		}
+		let moved = std::mem::take(&mut self.members[root_b.index()]);
+		self.members[root_a.index()].extend(moved);
+		self.parent[root_b.index()] = root_a;
+		true
+	}
+
+	/// Узел, в котором аргументы заменены на корни их классов.
+	fn canonical(&self, id: NodeId) -> Node {
+		match self.store.node(id) {
+			Node::AnyFunction { name, args } => {
+				Node::AnyFunction { name: *name, args: args.iter().map(|arg| self.find(*arg)).collect() }
+			},
+			Node::NamedFunction { name, args } => {
+				Node::NamedFunction { name: *name, args: args.iter().map(|arg| self.find(*arg)).collect() }
+			},
+			node => node.clone(),
+		}
+	}
+
+	/// Объединяет узлы с одной функцией и равными аргументами, пока такие есть.
+	fn rebuild(&mut self) {
+		loop {
+			let mut signatures: HashMap<Node, NodeId> = HashMap::new();
+			let mut merges = Vec::new();
+			for id in self.store.ids() {
+				match signatures.get(&self.canonical(id)) {
+					Some(other) if self.find(*other) != self.find(id) => merges.push((*other, id)),
+					Some(_) => {},
+					None => {
+						signatures.insert(self.canonical(id), id);
+					},
+				}
+			}
+			let mut changed = false;
+			for (a, b) in merges {
+				changed |= self.union(a, b, Justification::Congruence);
+			}
+			if !changed {
+				break;
+			}
+		}
+	}
+
+	fn same_head(&self, pattern: &Expression, id: NodeId) -> bool {
+		use ExpressionMeta::*;
+
+		match (&pattern.0, self.store.node(id)) {
+			(NamedFunction { name, args }, Node::NamedFunction { name: symbol, args: node_args }) => {
+				self.store.name(*symbol) == name && args.len() == node_args.len()
+			},
+			(NamedValue { name }, Node::NamedValue { name: symbol }) => self.store.name(*symbol) == name,
+			(IntegerValue { value }, Node::IntegerValue { value: node_value }) => value == node_value,
+			_ => false,
+		}
+	}
+
+	/// Все способы сопоставить паттерн формулы с каким-нибудь выражением класса `class`.
+	///
+	/// Это отдельный матчер, а не `binding::find_bindings`, потому что паттерн сопоставляется с классами, а не с одним выражением. Поддерживаются паттерны, функции, константы и числа, а `$f` нет: формулы с функциями в e-граф не попадают. Повторный паттерн совпадает, если его значения лежат в одном классе, поэтому `a-a` подходит к `x-(x+0)`, когда `x` и `x+0` уже равны, хотя `find_bindings` на этом выражении находит конфликт. Поэтому формула применяется не к найденному выражению, а к левой части, в которую подставлены классы, и объединяется с ним по конгруэнтности, так что шаги в объяснении проходят проверку.
+	fn ematch(&self, pattern: &Expression, class: NodeId, bindings: EBindings) -> Vec<EBindings> {
+		let class = self.find(class);
+		if let ExpressionMeta::Pattern { name } = &pattern.0 {
+			return match bindings.get(name) {
+				Some(bound) if self.find(*bound) != class => Vec::new(),
+				Some(_) => vec![bindings],
+				None => {
+					let mut bindings = bindings;
+					bindings.insert(name.clone(), class);
+					vec![bindings]
+				},
+			};
+		}
+
+		let pattern_args = match &pattern.0 {
+			ExpressionMeta::NamedFunction { args, .. } => &args[..],
+			_ => &[],
+		};
+		let mut result = Vec::new();
+		for member in &self.members[class.index()] {
+			if !self.same_head(pattern, *member) {
+				continue;
+			}
+			let mut partial = vec![bindings.clone()];
+			for (pattern_arg, arg) in pattern_args.iter().zip(self.store.node(*member).args()) {
+				partial = partial.into_iter().flat_map(|bindings| self.ematch(pattern_arg, *arg, bindings)).collect();
+			}
+			result.extend(partial);
+		}
+		result
+	}
+
+	/// Подставляет в паттерн формулы узлы вместо паттернов.
+	fn instantiate(&mut self, pattern: &Expression, bindings: &EBindings) -> NodeId {
+		use ExpressionMeta::*;
+
+		let node = match &pattern.0 {
+			Pattern { name } => return bindings[name],
+			NamedFunction { name, args } => Node::NamedFunction {
+				name: self.store.symbol(name),
+				args: args.iter().map(|arg| self.instantiate(arg, bindings)).collect(),
+			},
+			NamedValue { name } => Node::NamedValue { name: self.store.symbol(name) },
+			IntegerValue { value } => Node::IntegerValue { value: *value },
+			AnyFunction { .. } => unreachable!("formulas with functions are not used in e-graph"),
+		};
+		let result = self.store.add(node);
+		self.sync();
+		result
+	}
+
+	/// Путь по рёбрам объединений от узла `from` до узла `to` из того же класса.
+	fn path(&self, from: NodeId, to: NodeId) -> Vec<(NodeId, NodeId, Justification)> {
+		let mut previous: HashMap<NodeId, (NodeId, Justification)> = HashMap::new();
+		let mut visited = HashSet::new();
+		visited.insert(from);
+		let mut queue = VecDeque::new();
+		queue.push_back(from);
+		while let Some(current) = queue.pop_front() {
+			if current == to {
+				break;
+			}
+			for (next, justification) in &self.edges[current.index()] {
+				if visited.insert(*next) {
+					previous.insert(*next, (current, justification.clone()));
+					queue.push_back(*next);
+				}
+			}
+		}
+
+		let mut result = Vec::new();
+		let mut current = to;
+		while current != from {
+			let (before, justification) = previous[&current].clone();
+			result.push((before, current, justification));
+			current = before;
+		}
+		result.reverse();
+		result
+	}
+
+	/// Дописывает в `steps` шаги, которые превращают подвыражение `current` в позиции `position` из `from` в `to`.
+	fn explain(
+		&self,
+		from: NodeId,
+		to: NodeId,
+		position: &mut ExprPositionOwned,
+		current: &mut Expression,
+		global_formulas: &BTreeMap<FormulaPosition, Formula>,
+		steps: &mut Vec<PlainStep>,
+	) {
+		for (before, after, justification) in self.path(from, to) {
+			match justification {
+				Justification::Rule(rule) => {
+					let result = self.store.to_expression(after);
+					let mut bindings = Vec::new();
+					if let Some(formula) = global_formulas.get(&rule.formula) {
+						let (source, target) = if rule.left_to_right {
+							(&formula.left, &formula.right)
+						} else {
+							(&formula.right, &formula.left)
+						};
+						let mut storage = BindingStorage::default();
+						let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::new());
+						find_bindings(result.clone(), &target.pattern, &mut storage, &mut any_function_bindings)
+							.expect("formula was applied to this node");
+						for name in &source.unknown_patterns_names {
+							bindings.push(Binding::new(name.clone(), storage.get(name).unwrap().clone()));
+						}
+					}
+					steps.push(PlainStep {
+						expr: current.clone(),
+						position: position.clone(),
+						rule: to_parsing(&rule),
+						bindings,
+						function_bindings: Vec::new(),
+					});
+					*current.get_mut(position).unwrap() = result;
+				},
+				Justification::Congruence => {
+					let args = self.store.node(before).args().iter().zip(self.store.node(after).args());
+					for (index, (before, after)) in args.enumerate() {
+						position.0.push(index);
+						self.explain(*before, *after, position, current, global_formulas, steps);
+						position.0.pop();
+					}
+				},
+			}
+		}
+	}
+}
+
+/// Доказывает формулу `position` насыщением e-графа: все формулы применяются ко всем классам, пока левая и правая части не окажутся в одном классе. Коммутативность и ассоциативность не размножают выражения, как в поиске в ширину, потому что равные подвыражения хранятся один раз.
+///
+/// Используются формулы без условий и без `$f`, у которых все паттерны правой части есть в левой, а также условия самой формулы. Возвращает шаги одной цепочки от левой части, как и `search::prove`.
+pub fn prove(
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+	limits: &SearchLimits,
+) -> Result<Vec<PlainStep>, SearchError> {
+	let formula = global_formulas.get(position).ok_or(SearchError::FormulaNotFound)?;
+	if formula.left.pattern == formula.right.pattern {
+		return Err(SearchError::AlreadyEqual);
+	}
+
+	let mut rules = Vec::new();
+	for (position, used) in allowed_formulas(math, global_formulas, position) {
+		if !used.hypotheses.is_empty() || !used.left.anyfunction_names.is_empty() {
+			continue;
+		}
+		for (source, target, left_to_right) in [(&used.left, &used.right, true), (&used.right, &used.left, false)] {
+			if source.unknown_patterns_names.is_empty() && !matches!(source.pattern.0, ExpressionMeta::Pattern { .. }) {
+				rules.push((IndexedRule { formula: position.clone(), left_to_right }, &source.pattern, &target.pattern));
+			}
+		}
+	}
+
+	let mut graph = EGraph::default();
+	let left = graph.add(&formula.left.pattern);
+	let right = graph.add(&formula.right.pattern);
+	for (number, hypothesis) in formula.hypotheses.iter().enumerate() {
+		let (hypothesis_left, hypothesis_right) = (graph.add(&hypothesis.left), graph.add(&hypothesis.right));
+		let rule = IndexedRule {
+			formula: FormulaPosition { module_name: HYPOTHESIS_MODULE.to_string(), position: number + 1 },
+			left_to_right: true,
+		};
+		graph.union(hypothesis_left, hypothesis_right, Justification::Rule(rule));
+	}
+	graph.rebuild();
+
+	let started = Instant::now();
+	// Одна итерация на большом графе может быть долгой, поэтому время проверяется и внутри неё.
+	let check_time = |nodes: usize| match limits.timeout {
+		Some(timeout) if started.elapsed() > timeout => Err(SearchError::Timeout { timeout, nodes }),
+		_ => Ok(()),
+	};
+	while graph.find(left) != graph.find(right) {
+		let nodes = graph.store.len();
+
+		// Сначала ищутся все сопоставления, потом они применяются, чтобы порядок формул не влиял на результат итерации.
+		let mut applications = Vec::new();
+		let roots: Vec<NodeId> = graph.store.ids().filter(|id| graph.find(*id) == *id).collect();
+		for (index, (_, source, _)) in rules.iter().enumerate() {
+			for root in &roots {
+				check_time(nodes)?;
+				for bindings in graph.ematch(source, *root, EBindings::new()) {
+					applications.push((index, bindings));
+				}
+			}
+		}
+
+		let mut changed = false;
+		for (index, bindings) in applications {
+			let (rule, source, target) = &rules[index];
+			let from = graph.instantiate(source, &bindings);
+			let to = graph.instantiate(target, &bindings);
+			changed |= graph.union(from, to, Justification::Rule(rule.clone()));
+			if graph.store.len() > limits.max_nodes {
+				return Err(SearchError::NodeLimit { nodes: limits.max_nodes });
+			}
+		}
+		changed |= graph.store.len() != nodes;
+		graph.rebuild();
+		if !changed {
+			return Err(SearchError::Exhausted { nodes });
+		}
+	}
+
+	let mut current = formula.left.pattern.clone();
+	let mut steps = Vec::new();
+	graph.explain(left, right, &mut ExprPositionOwned(Vec::new()), &mut current, global_formulas, &mut steps);
+	Ok(steps)
+}
@@ -0,0 +1,237 @@
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+
+use thiserror::Error;
+
+use crate::expr::*;
+
+/// Точная дробь, знаменатель всегда положителен, а дробь несократима.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Rational {
+	numerator: i128,
+	denominator: i128,
+}
+
+/// `None`, если результат или остаток не помещаются в `i128`, например для `i128::MIN`.
+fn gcd(mut a: i128, mut b: i128) -> Option<i128> {
+	while b != 0 {
+		(a, b) = (b, a.checked_rem(b)?);
+	}
+	a.checked_abs()
+}
+
+impl Rational {
+	/// `None`, если знаменатель равен нулю или сокращённая дробь не помещается в `i128`.
+	pub fn new(numerator: i128, denominator: i128) -> Option<Rational> {
+		if denominator == 0 {
+			return None;
+		}
+		let divisor = gcd(numerator, denominator)?.checked_mul(denominator.signum())?;
+		Some(Rational { numerator: numerator.checked_div(divisor)?, denominator: denominator.checked_div(divisor)? })
+	}
+
+	pub fn integer(value: i128) -> Rational { Rational { numerator: value, denominator: 1 } }
+
+	pub fn numerator(&self) -> i128 { self.numerator }
+
+	pub fn denominator(&self) -> i128 { self.denominator }
+
+	pub fn is_zero(&self) -> bool { self.numerator == 0 }
+
+	pub fn signum(&self) -> Rational { Rational::integer(self.numerator.signum()) }
+
+	pub fn checked_neg(&self) -> Option<Rational> {
+		Some(Rational { numerator: self.numerator.checked_neg()?, denominator: self.denominator })
+	}
+
+	pub fn checked_abs(&self) -> Option<Rational> {
+		Some(Rational { numerator: self.numerator.checked_abs()?, denominator: self.denominator })
+	}
+
+	pub fn checked_add(&self, other: &Rational) -> Option<Rational> {
+		let left = self.numerator.checked_mul(other.denominator)?;
+		let right = other.numerator.checked_mul(self.denominator)?;
+		Rational::new(left.checked_add(right)?, self.denominator.checked_mul(other.denominator)?)
+	}
+
+	pub fn checked_sub(&self, other: &Rational) -> Option<Rational> { self.checked_add(&other.checked_neg()?) }
+
+	pub fn checked_mul(&self, other: &Rational) -> Option<Rational> {
+		let numerator = self.numerator.checked_mul(other.numerator)?;
+		Rational::new(numerator, self.denominator.checked_mul(other.denominator)?)
+	}
+
+	/// `None` при делении на ноль и при переполнении.
+	pub fn checked_div(&self, other: &Rational) -> Option<Rational> {
+		let numerator = self.numerator.checked_mul(other.denominator)?;
+		Rational::new(numerator, self.denominator.checked_mul(other.numerator)?)
+	}
+
+	pub fn checked_cmp(&self, other: &Rational) -> Option<Ordering> {
+		let left = self.numerator.checked_mul(other.denominator)?;
+		let right = other.numerator.checked_mul(self.denominator)?;
+		Some(left.cmp(&right))
+	}
+}
+
+impl fmt::Display for Rational {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.denominator == 1 {
+			write!(f, "{}", self.numerator)
+		} else {
+			write!(f, "{}/{}", self.numerator, self.denominator)
+		}
+	}
+}
+
+/// Значение выражения.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Value {
+	Number(Rational),
+	Bool(bool),
+	/// `$undefined`, например результат деления на ноль.
+	Undefined,
+}
+
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Value::Number(number) => write!(f, "{}", number),
+			Value::Bool(true) => write!(f, "$true"),
+			Value::Bool(false) => write!(f, "$false"),
+			Value::Undefined => write!(f, "$undefined"),
+		}
+	}
+}
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum EvalError {
+	#[error("pattern `{0}` has no value")]
+	UnboundPattern(String),
+	#[error("function `{name}` of {arguments_count} arguments has no interpretation")]
+	UnknownFunction { name: String, arguments_count: usize },
+	#[error("value `${0}` has no interpretation")]
+	UnknownValue(String),
+	#[error("`${0}` is any function, it can't be evaluated")]
+	AnyFunction(String),
+	#[error("number is too big")]
+	Overflow,
+}
+
+/// Смысл функции: значение по значениям аргументов.
+pub type Interpretation = fn(&[Value]) -> Result<Value, EvalError>;
+
+/// Таблица смыслов функций по имени и количеству аргументов. `Interpretations::default()` содержит встроенные функции, в неё можно добавить свои или заменить встроенные.
+#[derive(Clone, Debug)]
+pub struct Interpretations(BTreeMap<(String, usize), Interpretation>);
+
+impl Interpretations {
+	/// Таблица без функций.
+	pub fn empty() -> Interpretations { Interpretations(BTreeMap::new()) }
+
+	pub fn insert(&mut self, name: &str, arguments_count: usize, interpretation: Interpretation) {
+		self.0.insert((name.to_string(), arguments_count), interpretation);
+	}
+
+	pub fn remove(&mut self, name: &str, arguments_count: usize) {
+		self.0.remove(&(name.to_string(), arguments_count));
+	}
+
+	pub fn get(&self, name: &str, arguments_count: usize) -> Option<Interpretation> {
+		self.0.get(&(name.to_string(), arguments_count)).copied()
+	}
+}
+
+fn arithmetic(args: &[Value], f: fn(&Rational, &Rational) -> Option<Rational>) -> Result<Value, EvalError> {
+	match args {
+		[Value::Number(a), Value::Number(b)] => f(a, b).map(Value::Number).ok_or(EvalError::Overflow),
+		_ => Ok(Value::Undefined),
+	}
+}
+
+fn unary(args: &[Value], f: fn(&Rational) -> Option<Rational>) -> Result<Value, EvalError> {
+	match args {
+		[Value::Number(a)] => f(a).map(Value::Number).ok_or(EvalError::Overflow),
+		_ => Ok(Value::Undefined),
+	}
+}
+
+fn compare(args: &[Value], f: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+	match args {
+		[Value::Number(a), Value::Number(b)] => {
+			a.checked_cmp(b).map(|ordering| Value::Bool(f(ordering))).ok_or(EvalError::Overflow)
+		},
+		_ => Ok(Value::Undefined),
+	}
+}
+
+fn logic(args: &[Value], f: fn(bool, bool) -> bool) -> Result<Value, EvalError> {
+	match args {
+		[Value::Bool(a), Value::Bool(b)] => Ok(Value::Bool(f(*a, *b))),
+		_ => Ok(Value::Undefined),
+	}
+}
+
+/// Встроенные функции. Арифметика и сравнения определены только для чисел, логика только для `$true` и `$false`, иначе результат `$undefined`, поэтому `$undefined` из `a/0` проходит через любое выражение, как требуют `div.1` и `div.2`. `=` сравнивает значения как есть, поэтому `$undefined = $undefined` это `$true`, как требует `eq.2`.
+impl Default for Interpretations {
+	fn default() -> Interpretations {
+		use Value::*;
+
+		let mut result = Interpretations::empty();
+		result.insert("+", 2, |args| arithmetic(args, Rational::checked_add));
+		result.insert("-", 2, |args| arithmetic(args, Rational::checked_sub));
+		result.insert("*", 2, |args| arithmetic(args, Rational::checked_mul));
+		result.insert("/", 2, |args| match args {
+			[_, Number(b)] if b.is_zero() => Ok(Undefined),
+			_ => arithmetic(args, Rational::checked_div),
+		});
+		result.insert("negative", 1, |args| unary(args, Rational::checked_neg));
+		result.insert("abs", 1, |args| unary(args, Rational::checked_abs));
+		result.insert("sign", 1, |args| unary(args, |a| Some(a.signum())));
+		result.insert("=", 2, |args| Ok(Bool(args[0] == args[1])));
+		result.insert("!=", 2, |args| Ok(Bool(args[0] != args[1])));
+		result.insert("<", 2, |args| compare(args, Ordering::is_lt));
+		result.insert(">", 2, |args| compare(args, Ordering::is_gt));
+		result.insert("<=", 2, |args| compare(args, Ordering::is_le));
+		result.insert(">=", 2, |args| compare(args, Ordering::is_ge));
+		result.insert("not", 1, |args| match args {
+			[Bool(a)] => Ok(Bool(!a)),
+			_ => Ok(Undefined),
+		});
+		result.insert("&", 2, |args| logic(args, |a, b| a && b));
+		result.insert("|", 2, |args| logic(args, |a, b| a || b));
+		// Ветка выбирается условием, значение другой ветки не важно, даже если оно `$undefined`.
+		result.insert("part", 3, |args| match args {
+			[Bool(cond), then, otherwise] => Ok(if *cond { *then } else { *otherwise }),
+			_ => Ok(Undefined),
+		});
+		result
+	}
+}
+
+/// Вычисляет значение выражения, в котором паттерны заменены на `values`, а функции берутся из `interpretations`. Значения `$true`, `$false` и `$undefined` встроены.
+pub fn eval(
+	expr: &Expression,
+	values: &BTreeMap<String, Value>,
+	interpretations: &Interpretations,
+) -> Result<Value, EvalError> {
+	use ExpressionMeta::*;
+
+	match &expr.0 {
+		Pattern { name } => values.get(name).copied().ok_or_else(|| EvalError::UnboundPattern(name.clone())),
+		NamedFunction { name, args } => {
+			let interpretation = interpretations
+				.get(name, args.len())
+				.ok_or_else(|| EvalError::UnknownFunction { name: name.clone(), arguments_count: args.len() })?;
+			let args = args.iter().map(|arg| eval(arg, values, interpretations)).collect::<Result<Vec<_>, _>>()?;
+			interpretation(&args)
+		},
+		AnyFunction { name, .. } => Err(EvalError::AnyFunction(name.clone())),
+		NamedValue { name } => match name.as_str() {
+			"true" => Ok(Value::Bool(true)),
+			"false" => Ok(Value::Bool(false)),
+			"undefined" => Ok(Value::Undefined),
+			_ => Err(EvalError::UnknownValue(name.clone())),
+		},
+		IntegerValue { value } => Ok(Value::Number(Rational::integer(*value as i128))),
+	}
+}
@@ -0,0 +1,149 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+	binding::Formula,
+	parsing::{Math, NamedFormulas, ProofStep},
+	proof::{dependency_graph, tactic_rules, FormulaPosition},
+};
+
+/// Чем является формула в графе зависимостей.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum FormulaKind {
+	/// Формула без доказательства.
+	Axiom,
+	/// Формула с доказательством.
+	Proved,
+	/// На формулу ссылаются, но её нет в файле.
+	Missing,
+}
+
+impl FormulaKind {
+	fn as_str(self) -> &'static str {
+		match self {
+			FormulaKind::Axiom => "axiom",
+			FormulaKind::Proved => "proved",
+			FormulaKind::Missing => "missing",
+		}
+	}
+}
+
+/// Граф зависимостей, в котором все использования одной формулы в доказательстве другой собраны в одно ребро с весом.
+#[derive(Clone, Debug, Default)]
+pub struct DependencySummary {
+	pub nodes: BTreeMap<FormulaPosition, FormulaKind>,
+	/// `(кто использует, что используется) -> сколько шагов доказательства это использует`. Тактика считается шагами, в которые она развернулась.
+	pub edges: BTreeMap<(FormulaPosition, FormulaPosition), usize>,
+}
+
+impl DependencySummary {
+	pub fn new(math: &Math, global_formulas: &BTreeMap<FormulaPosition, Formula>) -> Self {
+		let graph = dependency_graph(math);
+		let mut result = DependencySummary::default();
+
+		for node in graph.node_indices() {
+			result.nodes.insert(graph[node].clone(), FormulaKind::Missing);
+		}
+		for NamedFormulas { name, formulas } in &math.0 {
+			for (index, full_formula) in formulas.iter().enumerate() {
+				let position = FormulaPosition { module_name: name.clone(), position: index + 1 };
+				let proof = match &full_formula.proof {
+					Some(proof) => proof,
+					None => {
+						result.nodes.insert(position, FormulaKind::Axiom);
+						continue;
+					},
+				};
+				result.nodes.insert(position.clone(), FormulaKind::Proved);
+
+				let written = proof.inner.steps().flat_map(ProofStep::used_formulas).map(|used| FormulaPosition {
+					module_name: used.inner.module_name.clone(),
+					position: used.inner.position,
+				});
+				let expanded = tactic_rules(&position, &full_formula.formula, proof, global_formulas);
+				for used in written.chain(expanded) {
+					*result.edges.entry((position.clone(), used)).or_insert(0) += 1;
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Формулы, сгруппированные по модулям, в порядке имён модулей.
+	fn modules(&self) -> BTreeMap<&str, Vec<(&FormulaPosition, FormulaKind)>> {
+		let mut result: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+		for (position, kind) in &self.nodes {
+			result.entry(&position.module_name[..]).or_default().push((position, *kind));
+		}
+		result
+	}
+
+	/// Экспорт в формат Graphviz DOT. Каждый модуль становится кластером, аксиомы рисуются прямоугольниками.
+	pub fn to_dot(&self) -> String {
+		let mut result = String::new();
+		writeln!(result, "digraph dependencies {{").unwrap();
+		writeln!(result, "\trankdir = BT;").unwrap();
+		for (module, nodes) in self.modules() {
+			writeln!(result, "\tsubgraph \"cluster_{}\" {{", module).unwrap();
+			writeln!(result, "\t\tlabel = \"{}\";", module).unwrap();
+			for (position, kind) in nodes {
+				let shape = match kind {
+					FormulaKind::Axiom => "box",
+					FormulaKind::Proved => "ellipse",
+					FormulaKind::Missing => "octagon",
+				};
+				writeln!(result, "\t\t\"{}\" [shape = {}];", position, shape).unwrap();
+			}
+			writeln!(result, "\t}}").unwrap();
+		}
+		for ((from, to), weight) in &self.edges {
+			writeln!(result, "\t\"{}\" -> \"{}\" [weight = {}, label = \"{}\"];", from, to, weight, weight).unwrap();
+		}
+		writeln!(result, "}}").unwrap();
+		result
+	}
+
+	/// Экспорт в JSON вида `{"nodes": [...], "edges": [...]}`.
+	pub fn to_json(&self) -> String {
+		let nodes: Vec<String> = self
+			.nodes
+			.iter()
+			.map(|(position, kind)| {
+				format!(
+					"{{\"id\": {}, \"module\": {}, \"position\": {}, \"kind\": \"{}\"}}",
+					json_string(&position.to_string()),
+					json_string(&position.module_name),
+					position.position,
+					kind.as_str(),
+				)
+			})
+			.collect();
+		let edges: Vec<String> = self
+			.edges
+			.iter()
+			.map(|((from, to), weight)| {
+				format!(
+					"{{\"from\": {}, \"to\": {}, \"weight\": {}}}",
+					json_string(&from.to_string()),
+					json_string(&to.to_string()),
+					weight,
+				)
+			})
+			.collect();
+		format!("{{\n\t\"nodes\": [\n\t\t{}\n\t],\n\t\"edges\": [\n\t\t{}\n\t]\n}}\n", nodes.join(",\n\t\t"), edges.join(",\n\t\t"))
+	}
+}
+
+fn json_string(s: &str) -> String {
+	let mut result = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => result.push_str("\\\""),
+			'\\' => result.push_str("\\\\"),
+			c if (c as u32) < 0x20 => write!(result, "\\u{:04x}", c as u32).unwrap(),
+			c => result.push(c),
+		}
+	}
+	result.push('"');
+	result
+}
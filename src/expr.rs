@@ -65,6 +65,9 @@ impl GetInnerExpression for Expression {
 	fn get_inner_expression_mut(&mut self) -> &mut ExpressionMeta<Self> { &mut self.0 }
 }
 
+/// Метапеременная — это паттерн, значение которого ещё не известно внутри доказательства и будет найдено унификацией на следующих шагах. В выражении выглядит как: `?x`. Такое имя можно записать в любом выражении, поэтому формулы с `?x` отвергает `Formula::reject_metavariables`, а унификация в доказательстве уточняет только метапеременные, которые создала сама проверка.
+pub fn is_metavariable(name: &str) -> bool { name.starts_with('?') }
+
 /// Положение в выражении.
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct ExprPositionOwned(pub Vec<usize>);
@@ -93,7 +96,7 @@ impl Borrow<ExprPosition> for ExprPositionOwned {
 impl Deref for ExprPositionOwned {
 	type Target = ExprPosition;
 
-	fn deref(&self) -> &Self::Target { &self.borrow() }
+	fn deref(&self) -> &Self::Target { self.borrow() }
 }
 
 /// Показывает в каком положении в массиве `ExprPosition` не было найдено то что нужно.
@@ -142,7 +145,7 @@ where Arg: GetInnerExpression
 	) {
 		use ExpressionMeta::*;
 
-		f(self, (&*current_position).borrow());
+		f(self, (*current_position).borrow());
 
 		let mut process_args = |args: &[Arg]| {
 			args.iter().enumerate().for_each(|(pos, arg)| {
@@ -153,7 +156,7 @@ where Arg: GetInnerExpression
 		};
 
 		match self.get_inner_expression_ref() {
-			AnyFunction { name: _, args } | NamedFunction { name: _, args } => process_args(&*args),
+			AnyFunction { name: _, args } | NamedFunction { name: _, args } => process_args(args),
 
 			Pattern { name: _ } | NamedValue { name: _ } | IntegerValue { value: _ } => {},
 		}
@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{binding::Formula, expr::*, proof::FormulaPosition};
+
+/// Одна сторона формулы, которую можно применить: `module.positionl` или `module.positionr`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct IndexedRule {
+	pub formula: FormulaPosition,
+	pub left_to_right: bool,
+}
+
+/// Символ в ключе дерева. Паттерны и `AnyFunction` формул превращаются в `Any`, потому что могут совпасть с любым поддеревом.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Key {
+	Any,
+	Pattern(String),
+	AnyFunction(String, usize),
+	NamedFunction(String, usize),
+	NamedValue(String),
+	IntegerValue(i64),
+}
+
+#[derive(Default, Debug, Clone)]
+struct IndexNode {
+	children: HashMap<Key, usize>,
+	any: Option<usize>,
+	rules: Vec<IndexedRule>,
+}
+
+/// Дерево различения (discrimination tree) по обеим сторонам всех формул. Позволяет найти формулы, которые могут примениться к выражению, не перебирая их все.
+///
+/// Индекс не учитывает повторы паттернов (`a-a` ищется как `_-_`), поэтому возвращает кандидатов, которых затем нужно проверить через `find_bindings`. Но если формула применима, то она точно будет среди кандидатов.
+#[derive(Debug, Clone)]
+pub struct FormulaIndex {
+	nodes: Vec<IndexNode>,
+}
+
+impl Default for FormulaIndex {
+	fn default() -> Self { FormulaIndex { nodes: vec![IndexNode::default()] } }
+}
+
+fn flatten(expr: &Expression, pattern_is_any: bool, result: &mut Vec<(Key, usize)>) {
+	use ExpressionMeta::*;
+
+	let index = result.len();
+	let (key, args) = match &expr.0 {
+		Pattern { .. } | AnyFunction { .. } if pattern_is_any => (Key::Any, &[][..]),
+		Pattern { name } => (Key::Pattern(name.clone()), &[][..]),
+		AnyFunction { name, args } => (Key::AnyFunction(name.clone(), args.len()), &args[..]),
+		NamedFunction { name, args } => (Key::NamedFunction(name.clone(), args.len()), &args[..]),
+		NamedValue { name } => (Key::NamedValue(name.clone()), &[][..]),
+		IntegerValue { value } => (Key::IntegerValue(*value), &[][..]),
+	};
+	result.push((key, 0));
+	for arg in args {
+		flatten(arg, pattern_is_any, result);
+	}
+	// Второй элемент: индекс, с которого начинается следующее после этого поддерева.
+	result[index].1 = result.len();
+}
+
+impl FormulaIndex {
+	pub fn new(formulas: &BTreeMap<FormulaPosition, Formula>) -> Self {
+		let mut result = Self::default();
+		for (position, formula) in formulas {
+			result.insert(&formula.left.pattern, IndexedRule { formula: position.clone(), left_to_right: true });
+			result.insert(&formula.right.pattern, IndexedRule { formula: position.clone(), left_to_right: false });
+		}
+		result
+	}
+
+	/// Добавляет сторону формулы `pattern` в индекс.
+	pub fn insert(&mut self, pattern: &Expression, rule: IndexedRule) {
+		let mut keys = Vec::new();
+		flatten(pattern, true, &mut keys);
+
+		let mut current = 0;
+		for (key, _) in keys {
+			let next = self.nodes.len();
+			let child = match key {
+				Key::Any => *self.nodes[current].any.get_or_insert(next),
+				key => *self.nodes[current].children.entry(key).or_insert(next),
+			};
+			if child == next {
+				self.nodes.push(IndexNode::default());
+			}
+			current = child;
+		}
+		self.nodes[current].rules.push(rule);
+	}
+
+	/// Возвращает все формулы, чья сторона может совпасть с выражением целиком.
+	pub fn candidates(&self, expr: &Expression) -> BTreeSet<IndexedRule> {
+		let mut keys = Vec::new();
+		flatten(expr, false, &mut keys);
+
+		let mut result = BTreeSet::new();
+		self.retrieve(0, &keys, 0, &mut result);
+		result
+	}
+
+	/// Возвращает кандидатов для каждого подвыражения.
+	pub fn candidates_everywhere(&self, expr: &Expression) -> Vec<(ExprPositionOwned, IndexedRule)> {
+		let mut result = Vec::new();
+		expr.travel_positions(|part, position| {
+			for rule in self.candidates(part) {
+				result.push((ExprPositionOwned(position.0.to_vec()), rule));
+			}
+		});
+		result
+	}
+
+	fn retrieve(&self, node: usize, keys: &[(Key, usize)], at: usize, result: &mut BTreeSet<IndexedRule>) {
+		let node = &self.nodes[node];
+		match keys.get(at) {
+			None => result.extend(node.rules.iter().cloned()),
+			Some((key, skip)) => {
+				if let Some(child) = node.children.get(key) {
+					self.retrieve(*child, keys, at + 1, result);
+				}
+				if let Some(any) = node.any {
+					self.retrieve(any, keys, *skip, result);
+				}
+			},
+		}
+	}
+}
@@ -0,0 +1,341 @@
+use std::{
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet},
+};
+
+use thiserror::Error;
+
+use crate::{
+	binding::Formula,
+	expr::*,
+	index::{FormulaIndex, IndexedRule},
+	parsing::{
+		self, clear_parsing_info, process_expression_parsing, FullFormula, Lemma, Math, Proof, ProofStep, Side,
+		StepPosition,
+	},
+	print::caret_for,
+	proof::{apply_proof_step, dependency_graph, hypotheses, lemma_formula, FormulaPosition, ProofContext},
+	search::{allowed_formulas, allowed_formulas_in, to_parsing},
+	unify::unify_metavariables,
+	utils::{char_index::get_char_range, joined_by::*, span::*},
+};
+
+#[derive(Debug, Error)]
+pub enum InferError {
+	#[error("no formula turns this expression into the next one")]
+	NotFound,
+	#[error(
+		"step is ambiguous, it can be {}, write position or formula",
+		candidates.iter().map(|(position, rule)| format!("`{}` at @{}", rule, position.0.iter().joined_by("."))).joined_by(", ")
+	)]
+	Ambiguous { candidates: Vec<(ExprPositionOwned, parsing::FormulaPosition)> },
+	#[error("next expression is not written, write position and formula of this step")]
+	NoNextExpression,
+	#[error("step written as `_` can't be inferred, write its position and formula")]
+	NotWritten,
+	#[error("position is not found")]
+	PositionNotFound,
+}
+
+/// У шага не записана позиция или формула.
+pub fn is_inferred(step: &ProofStep) -> bool { step.position.is_none() || step.used_formula.is_none() }
+
+fn rules_index(formulas: &BTreeMap<&FormulaPosition, &Formula>) -> FormulaIndex {
+	let mut result = FormulaIndex::default();
+	for (position, formula) in formulas {
+		result.insert(&formula.left.pattern, IndexedRule { formula: (*position).clone(), left_to_right: true });
+		result.insert(&formula.right.pattern, IndexedRule { formula: (*position).clone(), left_to_right: false });
+	}
+	result
+}
+
+/// Все применения формул к `expr`, которые стоит попробовать: в позиции `position`, если она записана, иначе везде. Условия теоремы и леммы не попадают в индекс, потому что меняются от доказательства к доказательству, их немного, и они пробуются везде.
+fn candidates(
+	expr: &Expression,
+	position: Option<&ExprPositionOwned>,
+	used_formula: Option<&parsing::FormulaPosition>,
+	index: &FormulaIndex,
+	context: &ProofContext,
+) -> Vec<(ExprPositionOwned, parsing::FormulaPosition)> {
+	let mut positions = Vec::new();
+	expr.travel_positions(|part, at| {
+		if position.is_none_or(|position| position.0 == at.0) {
+			positions.push((ExprPositionOwned(at.0.to_vec()), part.clone()));
+		}
+	});
+
+	let mut result = Vec::new();
+	for (position, part) in positions {
+		if let Some(used_formula) = used_formula {
+			result.push((position, used_formula.clone()));
+			continue;
+		}
+		// Формулы слева направо пробуются первыми, поэтому для `a+b <-> b+a` будет записано `l`.
+		let mut rules: Vec<IndexedRule> = index.candidates(&part).into_iter().collect();
+		rules.sort_by_key(|rule| !rule.left_to_right);
+		result.extend(rules.iter().map(|rule| (position.clone(), to_parsing(rule))));
+		let local = (1..=context.hypotheses.len())
+			.map(|number| (parsing::HYPOTHESIS_MODULE.to_string(), number))
+			.chain(context.lemmas.keys().map(|name| (name.clone(), 0)));
+		for (module_name, number) in local {
+			for left_to_right in [true, false] {
+				let rule = parsing::FormulaPosition {
+					module_name: module_name.clone(),
+					position: number,
+					left_to_right,
+					lemma: number == 0,
+				};
+				result.push((position.clone(), rule));
+			}
+		}
+	}
+	result
+}
+
+/// Находит позицию и формулу одного шага по его выражению и следующему выражению цепочки `next`.
+fn infer_step(
+	step: &mut ProofStep,
+	next: Option<Expression>,
+	index: &FormulaIndex,
+	context: &ProofContext,
+) -> Result<(), Spanned<InferError>> {
+	let span = if step.annotation.0.is_empty() { step.expr.span.clone() } else { step.annotation.clone() };
+	let written = step.expr.inner.clone().ok_or_else(|| Spanned::new(InferError::NotWritten, span.clone()))?;
+	let next = next.ok_or_else(|| Spanned::new(InferError::NoNextExpression, span.clone()))?;
+	let (expr, positions) = process_expression_parsing(written);
+	let char_range = |range: &LocalSpan| get_char_range(&step.string, range.0.clone());
+	let position = match &step.position {
+		Some(caret) => Some(
+			match &caret.inner {
+				StepPosition::Caret(caret) => positions
+					.iter()
+					.find(|(_, range)| char_range(range).is_some_and(|range| range == *caret))
+					.map(|(position, _)| position.clone()),
+				StepPosition::Path(path) => expr.get(path).ok().map(|_| path.clone()),
+			}
+			.ok_or_else(|| Spanned::new(InferError::PositionNotFound, caret.span.clone()))?,
+		),
+		None => None,
+	};
+
+	let used_formula = step.used_formula.as_ref().map(|used_formula| &used_formula.inner);
+	let mut found: Vec<(ExprPositionOwned, parsing::FormulaPosition)> = Vec::new();
+	for (position, rule) in candidates(&expr, position.as_ref(), used_formula, index, context) {
+		// Одна и та же формула в обе стороны в одной позиции, как `a+b <-> b+a`, считается одним применением.
+		let same = |(other_position, other): &(ExprPositionOwned, parsing::FormulaPosition)| {
+			*other_position == position && other.module_name == rule.module_name && other.position == rule.position
+		};
+		if found.iter().any(same) {
+			continue;
+		}
+		let mut candidate = step.clone();
+		candidate.used_formula = Some(Spanned::new(rule.clone(), span.clone()));
+		if let Ok(result) = apply_proof_step(expr.clone(), &position, &candidate, context) {
+			if unify_metavariables(&result, &next, &context.metavariables.borrow()).is_ok() {
+				found.push((position, rule));
+			}
+		}
+	}
+
+	match found.len() {
+		0 => Err(Spanned::new(InferError::NotFound, span)),
+		1 => {
+			let (position, rule) = found.pop().unwrap();
+			let range = positions.iter().find(|(x, _)| *x == position).expect("position is taken from this expression");
+			step.position = Some(Spanned::new(StepPosition::Caret(char_range(&range.1).unwrap()), span.clone()));
+			step.used_formula = Some(Spanned::new(rule, span));
+			Ok(())
+		},
+		_ => Err(Spanned::new(InferError::Ambiguous { candidates: found }, span)),
+	}
+}
+
+/// Выводит шаги доказательства леммы и добавляет её в контекст, так же, как это делает проверка.
+fn infer_lemma(
+	lemma: &mut Lemma,
+	index: &FormulaIndex,
+	context: &mut ProofContext,
+	errors: &mut Vec<Spanned<InferError>>,
+) {
+	let mut lemma_context = ProofContext {
+		global_formulas: context.global_formulas,
+		current: context.current.clone(),
+		hypotheses: hypotheses(&lemma.formula.inner),
+		lemmas: context.lemmas.clone(),
+		computed: Vec::new(),
+		tactic_rules: Vec::new(),
+		metavariables: RefCell::default(),
+	};
+	infer_proof(&lemma.formula.inner, &mut lemma.proof.inner, index, &mut lemma_context, errors);
+	if let Ok(formula) = lemma_formula(lemma) {
+		context.lemmas.insert(lemma.name.inner.clone(), formula);
+	}
+}
+
+/// Выводит шаги всех цепочек доказательства и его лемм. Следующее выражение для шага это выражение следующего шага или тактики, а для последнего шага единственной цепочки это другая часть формулы.
+fn infer_proof(
+	formula: &parsing::Formula,
+	proof: &mut Proof,
+	index: &FormulaIndex,
+	context: &mut ProofContext,
+	errors: &mut Vec<Spanned<InferError>>,
+) {
+	let single = proof.chains.len() == 1;
+	for chain in &mut proof.chains {
+		let chain = &mut chain.inner;
+		let target = match chain.side {
+			Side::Left => &formula.right,
+			Side::Right => &formula.left,
+		};
+		let target = if single { Some(clear_parsing_info(target.clone())) } else { None };
+
+		let mut lemmas = chain.lemmas.iter_mut().peekable();
+		for step_index in 0..chain.steps.len() {
+			// Леммы видны шагам, которые записаны после них.
+			let item_index = step_index + chain.tactics.iter().filter(|(before, _)| *before <= step_index).count();
+			while let Some((_, lemma)) = lemmas.next_if(|(before, _)| *before <= item_index) {
+				infer_lemma(lemma, index, context, errors);
+			}
+
+			if !is_inferred(&chain.steps[step_index]) {
+				continue;
+			}
+			let next = match chain.tactics.iter().find(|(before, _)| *before == step_index + 1) {
+				Some((_, tactic)) => Some(clear_parsing_info(tactic.expr.inner.clone())),
+				None => match chain.steps.get(step_index + 1) {
+					Some(next) => next.expr.inner.clone().map(clear_parsing_info),
+					None => target.clone(),
+				},
+			};
+			if let Err(error) = infer_step(&mut chain.steps[step_index], next, index, context) {
+				errors.push(error);
+			}
+		}
+		for (_, lemma) in lemmas {
+			infer_lemma(lemma, index, context, errors);
+		}
+	}
+}
+
+fn infer_full_formula(
+	full_formula: &mut FullFormula,
+	position: &FormulaPosition,
+	index: &FormulaIndex,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	errors: &mut Vec<Spanned<InferError>>,
+) {
+	let FullFormula { formula, proof, .. } = full_formula;
+	if let Some(proof) = proof {
+		let mut context = ProofContext {
+			global_formulas,
+			current: position.clone(),
+			hypotheses: hypotheses(&formula.inner),
+			lemmas: BTreeMap::new(),
+			computed: Vec::new(),
+			tactic_rules: Vec::new(),
+			metavariables: RefCell::default(),
+		};
+		infer_proof(&formula.inner, &mut proof.inner, index, &mut context, errors);
+	}
+}
+
+pub(crate) fn needs_inference(full_formula: &FullFormula) -> bool {
+	full_formula.proof.as_ref().is_some_and(|proof| proof.inner.steps().any(is_inferred))
+}
+
+/// Находит позиции и формулы шагов, у которых они не записаны или записаны как `?`, и записывает их в шаги. Подходит применение формулы к записанному выражению шага, результат которого совпадает со следующим выражением с точностью до метапеременных. Если подходит ни одно или несколько, это ошибка.
+///
+/// Используются те же формулы, что и в `prove`: все, кроме доказываемой и тех, что доказываются через неё, а также условия теоремы и леммы. После вывода найденные формулы видны в графе зависимостей как записанные, поэтому циклы через них находит `proofs_has_cycles`.
+pub fn infer_steps(
+	math: &mut Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Result<(), Vec<Spanned<InferError>>> {
+	infer_steps_where(math, global_formulas, |_| true)
+}
+
+/// То же самое, что и `infer_steps`, только для формул, на которых `filter` возвращает `true`. Используется, чтобы не выводить шаги доказательств из кэша.
+pub fn infer_steps_where(
+	math: &mut Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	filter: impl Fn(&FormulaPosition) -> bool,
+) -> Result<(), Vec<Spanned<InferError>>> {
+	let mut todo = Vec::new();
+	let mut graph = None;
+	for (module_index, named) in math.0.iter().enumerate() {
+		for (formula_index, full_formula) in named.formulas.iter().enumerate() {
+			let position = FormulaPosition { module_name: named.name.clone(), position: formula_index + 1 };
+			if needs_inference(full_formula) && filter(&position) {
+				let graph = graph.get_or_insert_with(|| dependency_graph(math));
+				let index = rules_index(&allowed_formulas_in(graph, global_formulas, &position));
+				todo.push((module_index, formula_index, position, index));
+			}
+		}
+	}
+
+	let mut errors = Vec::new();
+	for (module_index, formula_index, position, index) in todo {
+		let full_formula = &mut math.0[module_index].formulas[formula_index];
+		infer_full_formula(full_formula, &position, &index, global_formulas, &mut errors);
+	}
+	if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// То же самое, что и `infer_steps`, для формулы `position`, записанной отдельно от `math`, например, доказательства, которое только что напечатано.
+pub fn infer_formula(
+	full_formula: &mut FullFormula,
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+) -> Result<(), Vec<Spanned<InferError>>> {
+	if !needs_inference(full_formula) {
+		return Ok(());
+	}
+	let index = rules_index(&allowed_formulas(math, global_formulas, position));
+	let mut errors = Vec::new();
+	infer_full_formula(full_formula, position, &index, global_formulas, &mut errors);
+	if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Все шаги всех доказательств `math`.
+fn all_steps(math: &Math) -> impl Iterator<Item = &ProofStep> {
+	math.0
+		.iter()
+		.flat_map(|named| &named.formulas)
+		.filter_map(|formula| formula.proof.as_ref())
+		.flat_map(|proof| proof.inner.steps())
+}
+
+/// Выводит шаги `math`, которая прочитана из `input`, и дописывает найденные каретки и формулы в текст. Каретка пишется на отдельной строке с тем же отступом, что и у выражения. Возвращает новый текст и количество дописанных шагов.
+pub fn annotate(
+	input: &str,
+	math: &mut Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Result<(String, usize), Vec<Spanned<InferError>>> {
+	let inferred: BTreeSet<usize> =
+		all_steps(math).filter(|step| is_inferred(step)).map(|step| step.expr.span.0.start).collect();
+	infer_steps(math, global_formulas)?;
+
+	let mut replacements = Vec::new();
+	for step in all_steps(math).filter(|step| inferred.contains(&step.expr.span.0.start)) {
+		let (position, used_formula) = (step.position.as_ref().unwrap(), step.used_formula.as_ref().unwrap());
+		let expr_end = step.expr.span.0.end;
+		let start = expr_end + input[expr_end..].find(';').expect("expression of step ends with `;`") + 1;
+		let line_start = input[..step.expr.span.0.start].rfind('\n').map_or(0, |index| index + 1);
+		let indent: String = input[line_start..].chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+		// Если после выражения ничего не записано, у шага нет и завершающей `;`.
+		let end = if step.annotation.0.is_empty() { ";" } else { "" };
+		let position = match &position.inner {
+			StepPosition::Caret(range) => caret_for(range),
+			StepPosition::Path(path) => format!("@{}", path.0.iter().joined_by(".")),
+		};
+		let text = format!("\n{}{} {}{}", indent, position, used_formula.inner, end);
+		replacements.push((start..step.annotation.0.end, text));
+	}
+
+	let mut result = input.to_string();
+	replacements.sort_by_key(|(range, _)| range.start);
+	for (range, text) in replacements.iter().rev() {
+		result.replace_range(range.clone(), text);
+	}
+	Ok((result, replacements.len()))
+}
@@ -1,35 +1,59 @@
-use annotate_snippets::{
-	display_list::{DisplayList, FormatOptions},
-	snippet::{Annotation, AnnotationType, Snippet},
-};
+// Ошибки здесь несут выражения для сообщений пользователю, поэтому они большие.
+#![allow(clippy::result_large_err)]
 
 use crate::utils::span::peg_error_to_snippet;
 
+pub mod analyze;
 pub mod binding;
+pub mod cache;
+pub mod completion;
+pub mod dag;
+pub mod egraph;
+pub mod export;
+pub mod eval;
 pub mod expr;
+pub mod index;
+pub mod infer;
+pub mod materialize;
+pub mod minimize;
 pub mod parsing;
+pub mod print;
 pub mod proof;
+pub mod quickcheck;
+pub mod search;
+pub mod tactic;
+pub mod unify;
 pub mod utils;
 
-pub fn read_fpl(input: &str) -> Result<(), ()> {
-	use crate::{parsing::*, proof::*};
+#[allow(clippy::result_unit_err)]
+pub fn read_fpl(input: &str) -> Result<(), ()> { read_fpl_cached(input, &mut cache::ProofCache::default(), 0) }
 
-	let parsed_math = parser::math(&input).map_err(|err| peg_error_to_snippet(err, input))?;
+/// То же самое, что и `read_fpl`, но не проверяет повторно доказательства из кэша, а остальные проверяет в `threads` потоках.
+#[allow(clippy::result_unit_err)]
+pub fn read_fpl_cached(input: &str, cache: &mut cache::ProofCache, threads: usize) -> Result<(), ()> {
+	use crate::{infer::infer_steps_where, parsing::*, proof::*};
+
+	let mut parsed_math = parser::math(input).map_err(|err| peg_error_to_snippet(err, input))?;
 
 	let math = read_math(&parsed_math).map_err(|errs| {
 		for err in errs {
 			err.print_error_snippet(input);
 		}
 	})?;
-	proofs_has_cycles(&parsed_math).map_err(|text| {
-		let snippet = Snippet {
-			title: Some(Annotation { label: Some(text), id: None, annotation_type: AnnotationType::Error }),
-			opt: FormatOptions { color: true, ..Default::default() },
-			..Snippet::default()
-		};
-		println!("{}", DisplayList::from(snippet));
+	// Ключи считаются до вывода шагов, а шаги выводятся только в доказательствах, которых нет в кэше.
+	let keys = cache::ProofCache::keys(input, &parsed_math, &math);
+	let is_cached = |position: &proof::FormulaPosition| keys.get(position).is_some_and(|key| cache.contains(*key));
+	infer_steps_where(&mut parsed_math, &math, |position| !is_cached(position)).map_err(|errs| {
+		for err in errs {
+			err.print_error_snippet(input);
+		}
+	})?;
+	proofs_has_cycles(&parsed_math).map_err(|errs| {
+		for err in errs {
+			err.print_error_snippet(input);
+		}
 	})?;
-	is_proofs_correct(&parsed_math, &math).map_err(|errs| {
+	cache.is_proofs_correct(&keys, &parsed_math, &math, threads).map_err(|errs| {
 		for err in errs {
 			err.print_error_snippet(input);
 		}
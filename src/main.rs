@@ -1,11 +1,482 @@
-use fopply::read_fpl;
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf, str::FromStr, time::Duration};
 
-fn check_math() -> Result<(), ()> {
-	Ok(read_fpl(&std::fs::read_to_string("fpl/math.fpl").map_err(|_| println!("can't read `math.fpl`"))?)?)
+use fopply::{
+	cache::ProofCache,
+	completion::{self, module_equations, CompletionError, CompletionLimits, TermOrder},
+	eval::Interpretations,
+	export::DependencySummary,
+	infer::{infer_formula, infer_steps},
+	binding::Formula,
+	parsing::{clear_parsing_info, parser, FullFormula, Math},
+	print::proof_block,
+	proof::{is_proof_correct, read_math, FormulaPosition},
+	quickcheck::Outcome,
+	read_fpl_cached,
+	search::SearchLimits,
+	utils::span::{peg_error_to_snippet, Spanned},
+};
+
+const DEFAULT_FILE: &str = "fpl/math.fpl";
+
+const USAGE: &str = "usage: fopply [command] [options] [file]
+
+File is `fpl/math.fpl` by default.
+
+commands:
+	check                  check all proofs, this is the default command
+	  --cache <path>       file with already checked proofs, `<file>.cache` by default, `none` to disable
+	  --threads <n>        number of threads to check proofs, all cores by default
+	graph --format <f>     print dependency graph of formulas, <f> is `dot` or `json`
+	prove <formula>        search for a proof of formula like `sub.7` and print it
+	  --nodes <n>          how many expressions to visit, 20000 by default
+	  --timeout <seconds>  stop searching after this time
+	  --backend <b>        `bfs` for search from both sides, `egraph` for equality saturation, `bfs` by default
+	minimize <formula>     shorten proof of formula, the file is rewritten
+	  --nodes <n>          how many expressions to visit for each shortcut, 2000 by default
+	  --timeout <seconds>  stop each shortcut search after this time
+	annotate               write carets and formulas of steps where they are omitted or `?`, the file is rewritten
+	materialize            write expressions computed instead of `_`, the file is rewritten
+	complete <modules>     orient formulas of modules like `sum,mul` into rules and complete them by Knuth-Bendix
+	  --order <o>          `lpo` or `kbo`, `lpo` by default
+	  --precedence <list>  function names from greater to smaller, like `-,*,+,0`
+	  --weights <list>     weights of functions for `kbo`, like `*=2,-=3`, 1 by default
+	  --rules <n>          stop when there are more rules, 100 by default
+	  --timeout <seconds>  stop after this time, 10 by default
+	analyze <modules>      check that formulas of modules oriented from left to right are terminating and confluent
+	  --reverse <list>     formulas that are oriented from right to left, like `sum.2,mul.1`
+	  --steps <n>          how many rewrites to make for each side of critical pair, 1000 by default
+	  --order, --precedence and --weights are the same as for `complete`
+	quickcheck             compare sides of all formulas at random values of patterns
+	  --tests <n>          how many sets of values to check for each formula, 100 by default
+	  --seed <n>           seed of random values, 1 by default
+	eval <expr> <values>   compute expression with values of patterns like `a=1/2 b=$true`, the file is not read";
+
+/// Аргументы командной строки после имени команды: `--name value` и позиционные.
+struct Args {
+	positional: Vec<String>,
+	options: BTreeMap<String, String>,
+}
+
+impl Args {
+	fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+		let mut positional = Vec::new();
+		let mut options = BTreeMap::new();
+		let mut args = args.peekable();
+		while let Some(arg) = args.next() {
+			if let Some(name) = arg.strip_prefix("--") {
+				let value = args.next().ok_or_else(|| format!("option `--{}` requires a value", name))?;
+				options.insert(name.to_string(), value);
+			} else {
+				positional.push(arg);
+			}
+		}
+		Ok(Args { positional, options })
+	}
+
+	fn file(&self) -> &str { self.positional.first().map(String::as_str).unwrap_or(DEFAULT_FILE) }
+
+	fn option(&self, name: &str) -> Option<&str> { self.options.get(name).map(String::as_str) }
+
+	fn number_option<T: FromStr>(&self, name: &str) -> Result<Option<T>, ()> {
+		match self.option(name).map(str::parse::<T>) {
+			Some(Ok(value)) => Ok(Some(value)),
+			Some(Err(_)) => {
+				println!("`--{}` should be a number", name);
+				Err(())
+			},
+			None => Ok(None),
+		}
+	}
+}
+
+fn read_file(path: &str) -> Result<String, ()> {
+	std::fs::read_to_string(path).map_err(|_| println!("can't read `{}`", path))
+}
+
+fn check(args: &Args) -> Result<(), ()> {
+	let input = read_file(args.file())?;
+	let cache_path = match args.option("cache") {
+		Some("none") => None,
+		Some(path) => Some(PathBuf::from(path)),
+		None => Some(PathBuf::from(format!("{}.cache", args.file()))),
+	};
+	let threads = args.number_option("threads")?.unwrap_or(0);
+	let mut cache = cache_path.as_deref().map(ProofCache::load).unwrap_or_default();
+
+	let result = read_fpl_cached(&input, &mut cache, threads);
+	// Верные доказательства сохраняются даже если в других есть ошибки.
+	if let Some(path) = &cache_path {
+		if cache.save(path).is_err() {
+			println!("can't write cache to `{}`", path.display());
+		}
+	}
+	result?;
+	println!(
+		"`{}` is OK, {} proofs checked, {} taken from cache",
+		args.file(),
+		cache.checked,
+		cache.len() - cache.checked
+	);
+	Ok(())
+}
+
+fn graph(args: &Args) -> Result<(), ()> {
+	let input = read_file(args.file())?;
+	let (math, formulas) = parse_math(&input)?;
+	let summary = DependencySummary::new(&math, &formulas);
+	match args.option("format").unwrap_or("dot") {
+		"dot" => print!("{}", summary.to_dot()),
+		"json" => print!("{}", summary.to_json()),
+		format => {
+			println!("unknown graph format `{}`, expected `dot` or `json`", format);
+			return Err(());
+		},
+	}
+	Ok(())
+}
+
+/// `sub.7` в позицию формулы.
+fn formula_position(name: &str) -> Result<FormulaPosition, ()> {
+	let position = name.split_once('.').and_then(|(module_name, position)| {
+		Some(FormulaPosition { module_name: module_name.to_string(), position: position.parse().ok()? })
+	});
+	position.ok_or_else(|| println!("`{}` is not a formula name like `sub.7`", name))
+}
+
+/// Формула из первого позиционного аргумента и файл из второго.
+fn formula_and_file(args: &Args) -> Result<(FormulaPosition, &str), ()> {
+	let position = match args.positional.first() {
+		Some(name) => formula_position(name)?,
+		None => {
+			println!("formula is not given\n\n{}", USAGE);
+			return Err(());
+		},
+	};
+	Ok((position, args.positional.get(1).map(String::as_str).unwrap_or(DEFAULT_FILE)))
+}
+
+/// Модули через запятую из первого позиционного аргумента и файл из второго.
+fn modules_and_file(args: &Args) -> Result<(Vec<String>, &str), ()> {
+	let modules = match args.positional.first() {
+		Some(modules) => modules.split(',').map(|name| name.trim().to_string()).collect(),
+		None => {
+			println!("modules are not given\n\n{}", USAGE);
+			return Err(());
+		},
+	};
+	Ok((modules, args.positional.get(1).map(String::as_str).unwrap_or(DEFAULT_FILE)))
+}
+
+fn term_order(args: &Args) -> Result<TermOrder, ()> {
+	let precedence = args.option("precedence").map_or_else(Vec::new, |list| list.split(',').map(str::to_string).collect());
+	match args.option("order").unwrap_or("lpo") {
+		"lpo" => Ok(TermOrder::Lpo { precedence }),
+		"kbo" => {
+			let mut weights = BTreeMap::new();
+			for item in args.option("weights").into_iter().flat_map(|list| list.split(',')) {
+				match item.rsplit_once('=').map(|(name, weight)| (name, weight.parse::<usize>())) {
+					Some((name, Ok(weight))) if weight > 0 => weights.insert(name.to_string(), weight),
+					_ => {
+						println!("`{}` should be a function name and a positive weight, like `*=2`", item);
+						return Err(());
+					},
+				};
+			}
+			Ok(TermOrder::Kbo { precedence, weights })
+		},
+		order => {
+			println!("unknown order `{}`, expected `lpo` or `kbo`", order);
+			Err(())
+		},
+	}
+}
+
+fn search_limits(args: &Args, max_nodes: usize) -> Result<SearchLimits, ()> {
+	Ok(SearchLimits {
+		max_nodes: args.number_option("nodes")?.unwrap_or(max_nodes),
+		timeout: args.number_option("timeout")?.map(Duration::from_secs_f64),
+	})
+}
+
+fn print_errors<T: Display>(input: &str) -> impl Fn(Vec<Spanned<T>>) + '_ {
+	move |errs| {
+		for err in errs {
+			err.print_error_snippet(input);
+		}
+	}
+}
+
+/// Формулы файла, у шагов которых уже найдены пропущенные каретки и формулы.
+fn parse_math(input: &str) -> Result<(Math, BTreeMap<FormulaPosition, Formula>), ()> {
+	let mut math = parser::math(input).map_err(|err| peg_error_to_snippet(err, input))?;
+	let formulas = read_math(&math).map_err(print_errors(input))?;
+	infer_steps(&mut math, &formulas).map_err(print_errors(input))?;
+	Ok((math, formulas))
+}
+
+/// Текст формулы `position` с доказательством `proof`, который проверен так же, как записанный вручную.
+fn checked_formula_text(
+	input: &str,
+	math: &Math,
+	formulas: &BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+	proof: &str,
+) -> Result<String, ()> {
+	let full_formula = math.get(&position.module_name, position.position).unwrap();
+	let text = format!("{}. {} {};", position.position, &input[full_formula.formula.span.0.clone()], proof);
+	let mut parsed = parser::full_formula(&text).expect("printed proof should be parsed back");
+	infer_formula(&mut parsed, math, formulas, position).map_err(|errs| {
+		println!("new proof is wrong:");
+		print_errors(&text)(errs);
+	})?;
+	is_proof_correct(position, &parsed.formula, parsed.proof.as_ref().unwrap(), formulas).map_err(|errs| {
+		println!("new proof is wrong:");
+		for err in errs {
+			err.print_error_snippet(&text);
+		}
+	})?;
+	Ok(text)
 }
 
+fn prove(args: &Args) -> Result<(), ()> {
+	let (position, file) = formula_and_file(args)?;
+	let limits = search_limits(args, SearchLimits::default().max_nodes)?;
+	let input = read_file(file)?;
+	let (math, formulas) = parse_math(&input)?;
+	let steps = match args.option("backend").unwrap_or("bfs") {
+		"bfs" => fopply::search::prove(&math, &formulas, &position, &limits),
+		"egraph" => fopply::egraph::prove(&math, &formulas, &position, &limits),
+		backend => {
+			println!("unknown backend `{}`, expected `bfs` or `egraph`", backend);
+			return Err(());
+		},
+	};
+	let steps = steps.map_err(|err| println!("{}", err))?;
+	println!("{}", checked_formula_text(&input, &math, &formulas, &position, &proof_block(&steps))?);
+	Ok(())
+}
+
+fn minimize(args: &Args) -> Result<(), ()> {
+	let (position, file) = formula_and_file(args)?;
+	let limits = search_limits(args, 2_000)?;
+	let input = read_file(file)?;
+	let (math, formulas) = parse_math(&input)?;
+
+	// Неверное доказательство не сокращается: сначала его надо исправить.
+	if let Some(FullFormula { formula, proof: Some(proof), .. }) = math.get(&position.module_name, position.position) {
+		is_proof_correct(&position, formula, proof, &formulas).map_err(|errs| {
+			for err in errs {
+				err.print_error_snippet(&input);
+			}
+		})?;
+	}
+	let minimized = fopply::minimize::minimize(&input, &math, &formulas, &position, &limits)
+		.map_err(|err| println!("{}", err))?;
+	if minimized.after == minimized.before {
+		println!("proof of `{}` is already minimal, {} steps", position, minimized.before);
+		return Ok(());
+	}
+	checked_formula_text(&input, &math, &formulas, &position, &minimized.text)?;
+
+	let span = minimized.span.0;
+	let output = format!("{}{}{}", &input[..span.start], minimized.text, &input[span.end..]);
+	std::fs::write(file, output).map_err(|_| println!("can't write `{}`", file))?;
+	println!("proof of `{}` is minimized from {} to {} steps", position, minimized.before, minimized.after);
+	Ok(())
+}
+
+fn annotate(args: &Args) -> Result<(), ()> {
+	let input = read_file(args.file())?;
+	let mut math = parser::math(&input).map_err(|err| peg_error_to_snippet(err, &input))?;
+	let formulas = read_math(&math).map_err(print_errors(&input))?;
+	let (output, count) = fopply::infer::annotate(&input, &mut math, &formulas).map_err(print_errors(&input))?;
+	if count != 0 {
+		std::fs::write(args.file(), output).map_err(|_| println!("can't write `{}`", args.file()))?;
+	}
+	println!("{} steps of `{}` are annotated", count, args.file());
+	Ok(())
+}
+
+fn materialize(args: &Args) -> Result<(), ()> {
+	let input = read_file(args.file())?;
+	let (math, formulas) = parse_math(&input)?;
+	let (output, count) = fopply::materialize::materialize(&input, &math, &formulas).map_err(|errs| {
+		for err in errs {
+			err.print_error_snippet(&input);
+		}
+	})?;
+	if count != 0 {
+		std::fs::write(args.file(), output).map_err(|_| println!("can't write `{}`", args.file()))?;
+	}
+	println!("{} expressions of `{}` are written", count, args.file());
+	Ok(())
+}
+
+fn complete(args: &Args) -> Result<(), ()> {
+	let (modules, file) = modules_and_file(args)?;
+	let order = term_order(args)?;
+	let default = CompletionLimits::default();
+	let limits = CompletionLimits {
+		max_rules: args.number_option("rules")?.unwrap_or(default.max_rules),
+		timeout: args.number_option("timeout")?.map(Duration::from_secs_f64).or(default.timeout),
+	};
+	let input = read_file(file)?;
+	let (_, formulas) = parse_math(&input)?;
+	if let Some(module) = modules.iter().find(|module| !formulas.keys().any(|x| x.module_name == **module)) {
+		println!("module `{}` is not found", module);
+		return Err(());
+	}
+
+	let (equations, skipped) = module_equations(&formulas, &modules);
+	for position in skipped {
+		println!("`{}` is skipped, it has hypotheses or `$f`", position);
+	}
+	match completion::complete(equations.into_iter().map(|(_, left, right)| (left, right)).collect(), &order, &limits) {
+		Ok(rules) => {
+			println!("{} rules, they are terminating and confluent:", rules.len());
+			for rule in rules {
+				println!("\t{}", rule);
+			}
+			Ok(())
+		},
+		Err(err) => {
+			println!("{}", err);
+			let (CompletionError::Unorientable { rules, .. }
+			| CompletionError::TooManyRules { rules, .. }
+			| CompletionError::Timeout { rules, .. }) = err;
+			println!("rules found before that:");
+			for rule in rules {
+				println!("\t{}", rule);
+			}
+			Err(())
+		},
+	}
+}
+
+fn analyze(args: &Args) -> Result<(), ()> {
+	let (modules, file) = modules_and_file(args)?;
+	let order = term_order(args)?;
+	let reversed = match args.option("reverse") {
+		Some(list) => list.split(',').map(formula_position).collect::<Result<Vec<_>, ()>>()?,
+		None => Vec::new(),
+	};
+	let steps = args.number_option("steps")?.unwrap_or(1000);
+	let input = read_file(file)?;
+	let (_, formulas) = parse_math(&input)?;
+	if let Some(module) = modules.iter().find(|module| !formulas.keys().any(|x| x.module_name == **module)) {
+		println!("module `{}` is not found", module);
+		return Err(());
+	}
+
+	let analysis = fopply::analyze::analyze(&formulas, &modules, &reversed, &order, steps);
+	for position in &analysis.skipped {
+		println!("`{}` is skipped, it has hypotheses or `$f`", position);
+	}
+	for issue in &analysis.issues {
+		println!("warning: {}", issue);
+	}
+	println!(
+		"{} rules, {}, {} critical pairs, {}",
+		analysis.rules.len(),
+		if analysis.is_terminating() { "terminating" } else { "termination is not proved" },
+		analysis.critical_pairs,
+		if analysis.is_locally_confluent() { "all are joinable" } else { "some are not joinable" },
+	);
+	if analysis.issues.is_empty() { Ok(()) } else { Err(()) }
+}
+
+fn quickcheck(args: &Args) -> Result<(), ()> {
+	let tests = args.number_option("tests")?.unwrap_or(100);
+	let seed = args.number_option("seed")?.unwrap_or(1);
+	let input = read_file(args.file())?;
+	let (math, formulas) = parse_math(&input)?;
+
+	let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+	for (position, outcome) in fopply::quickcheck::quickcheck(&formulas, &Interpretations::default(), tests, seed) {
+		match outcome {
+			Outcome::Passed => passed += 1,
+			Outcome::Failed(counterexample) => {
+				let axiom = math.get(&position.module_name, position.position).is_some_and(|x| x.proof.is_none());
+				println!("{} {}", if axiom { "axiom" } else { "proved formula" }, counterexample);
+				failed += 1;
+			},
+			Outcome::Skipped(error) => {
+				println!("`{}` is skipped: {}", position, error);
+				skipped += 1;
+			},
+			Outcome::Vacuous => {
+				println!("`{}` is skipped: its hypotheses are false at all tried values", position);
+				skipped += 1;
+			},
+		}
+	}
+	println!("{} formulas passed, {} failed, {} skipped", passed, failed, skipped);
+	if failed == 0 { Ok(()) } else { Err(()) }
+}
+
+/// Значение выражения из первого позиционного аргумента. Значения паттернов тоже записываются выражениями, например `a=-1/2`.
+fn eval(args: &Args) -> Result<(), ()> {
+	let interpretations = Interpretations::default();
+	let parse = |text: &str| {
+		parser::expr_normalized(text).map(clear_parsing_info).map_err(|err| println!("can't parse `{}`, {}", text, err))
+	};
+	let eval = |text: &str, values: &BTreeMap<_, _>| {
+		fopply::eval::eval(&parse(text)?, values, &interpretations).map_err(|err| println!("{}", err))
+	};
+
+	let (expr, values) = match args.positional.split_first() {
+		Some(split) => split,
+		None => {
+			println!("expression is not given\n\n{}", USAGE);
+			return Err(());
+		},
+	};
+	let mut pattern_values = BTreeMap::new();
+	for value in values {
+		let (name, value) = value.split_once('=').ok_or_else(|| println!("`{}` is not a value like `a=1/2`", value))?;
+		pattern_values.insert(name.trim().to_string(), eval(value.trim(), &BTreeMap::new())?);
+	}
+	println!("{}", eval(expr, &pattern_values)?);
+	Ok(())
+}
+
+type Command = fn(&Args) -> Result<(), ()>;
+
+const COMMANDS: &[(&str, Command)] = &[
+	("check", check),
+	("graph", graph),
+	("prove", prove),
+	("minimize", minimize),
+	("annotate", annotate),
+	("materialize", materialize),
+	("complete", complete),
+	("analyze", analyze),
+	("quickcheck", quickcheck),
+	("eval", eval),
+];
+
 fn main() {
-	if let Ok(_) = check_math() {
-		println!("`math.fpl` is OK");
+	let mut args = std::env::args().skip(1).peekable();
+	if let Some("help") | Some("--help") | Some("-h") = args.peek().map(String::as_str) {
+		println!("{}", USAGE);
+		return;
+	}
+	let command = match COMMANDS.iter().find(|(name, _)| args.peek().map(|arg| arg == name).unwrap_or(false)) {
+		Some((_, command)) => {
+			args.next();
+			*command
+		},
+		None => check,
+	};
+	let args = match Args::parse(args) {
+		Ok(args) => args,
+		Err(text) => {
+			println!("{}\n\n{}", text, USAGE);
+			std::process::exit(2);
+		},
+	};
+
+	if command(&args).is_err() {
+		std::process::exit(1);
 	}
 }
@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+
+use crate::{
+	binding::Formula,
+	parsing::{Math, NamedFormulas},
+	print::to_string,
+	proof::{computed_expressions, FormulaPosition, ProofStepError},
+};
+
+/// Записывает в текст `input`, из которого прочитана `math`, выражения, посчитанные вместо `_`. Позиции `@0.1` остаются как есть, они верны и для записанного выражения. Возвращает новый текст и количество записанных выражений.
+pub fn materialize(
+	input: &str,
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Result<(String, usize), Vec<ProofStepError>> {
+	let mut replacements = Vec::new();
+	let mut errors = Vec::new();
+	for NamedFormulas { name, formulas } in &math.0 {
+		for full_formula in formulas {
+			let proof = match &full_formula.proof {
+				Some(proof) => proof,
+				None => continue,
+			};
+			let position =
+				FormulaPosition { module_name: name.clone(), position: full_formula.position.inner as usize };
+			match computed_expressions(&position, &full_formula.formula, proof, global_formulas) {
+				Ok(computed) => replacements.extend(computed),
+				Err(errs) => errors.extend(errs),
+			}
+		}
+	}
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+
+	let mut result = input.to_string();
+	replacements.sort_by_key(|(span, _)| span.0.start);
+	for (span, expr) in replacements.iter().rev() {
+		result.replace_range(span.0.clone(), &to_string(expr));
+	}
+	Ok((result, replacements.len()))
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::{
+	binding::Formula,
+	expr::*,
+	parsing::{clear_parsing_info, Math, Side},
+	print::PlainStep,
+	proof::FormulaPosition,
+	search::{PathSearch, SearchLimits},
+	utils::span::GlobalSpan,
+};
+
+#[derive(Debug, Error)]
+pub enum MinimizeError {
+	#[error("formula is not found")]
+	FormulaNotFound,
+	#[error("formula has no proof")]
+	NoProof,
+	#[error("only proofs of one chain without lemmas, tactics and `_` steps can be minimized")]
+	Unsupported,
+}
+
+/// Шаг минимизированного доказательства.
+enum Item {
+	/// Шаг исходного доказательства с этим номером, он переносится как записан.
+	Written(usize),
+	Found(PlainStep),
+}
+
+/// Доказательство после минимизации.
+#[derive(Debug)]
+pub struct Minimized {
+	/// Где в тексте записано доказательство.
+	pub span: GlobalSpan,
+	/// Новый текст доказательства, которым заменяется `input[span]`.
+	pub text: String,
+	pub before: usize,
+	pub after: usize,
+}
+
+fn has_metavariables(expr: &Expression) -> bool { expr.get_pattern_names().iter().any(|name| is_metavariable(name)) }
+
+/// Сокращает доказательство формулы `position`: убирает шаги, после которых цепочка возвращается к уже встреченному выражению, а участки, между концами которых `PathSearch` находит путь короче, заменяет найденными шагами. Результат надо проверить: если шаг уточнял метапеременные следующим выражением, после сокращения он может перестать сходиться.
+pub fn minimize(
+	input: &str,
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+	limits: &SearchLimits,
+) -> Result<Minimized, MinimizeError> {
+	let full_formula = math.get(&position.module_name, position.position).ok_or(MinimizeError::FormulaNotFound)?;
+	let formula = global_formulas.get(position).ok_or(MinimizeError::FormulaNotFound)?;
+	let proof = full_formula.proof.as_ref().ok_or(MinimizeError::NoProof)?;
+	let chain = match proof.inner.chains.as_slice() {
+		[chain] if chain.inner.lemmas.is_empty() && chain.inner.tactics.is_empty() => chain,
+		_ => return Err(MinimizeError::Unsupported),
+	};
+	let steps = &chain.inner.steps;
+	let target = match chain.inner.side {
+		Side::Left => &formula.right.pattern,
+		Side::Right => &formula.left.pattern,
+	};
+
+	// `exprs[i]` — выражение перед шагом `items[i]`, последнее выражение — цель цепочки.
+	let mut items: Vec<Item> = (0..steps.len()).map(Item::Written).collect();
+	let mut exprs: Vec<Expression> = steps
+		.iter()
+		.map(|step| step.expr.inner.clone().map(clear_parsing_info))
+		.collect::<Option<_>>()
+		.ok_or(MinimizeError::Unsupported)?;
+	exprs.push(target.clone());
+
+	// Петли: если выражение встречается дважды, всё между ними лишнее.
+	'loops: loop {
+		for i in 0..exprs.len() {
+			if has_metavariables(&exprs[i]) {
+				continue;
+			}
+			if let Some(j) = (i + 1..exprs.len()).rev().find(|j| exprs[*j] == exprs[i]) {
+				items.drain(i..j);
+				exprs.drain(i..j);
+				continue 'loops;
+			}
+		}
+		break;
+	}
+
+	// Короткие пути: для каждого выражения ищется самый дальний участок, который можно пройти быстрее.
+	let search = PathSearch::new(math, global_formulas, position).map_err(|_| MinimizeError::FormulaNotFound)?;
+	let mut i = 0;
+	while i < items.len() {
+		if !has_metavariables(&exprs[i]) {
+			for j in (i + 2..exprs.len()).rev() {
+				if has_metavariables(&exprs[j]) {
+					continue;
+				}
+				if let Ok(found) = search.find(&exprs[i], &exprs[j], limits) {
+					if found.len() < j - i {
+						exprs.splice(i + 1..j, found.iter().skip(1).map(|step| step.expr.clone()).collect::<Vec<_>>());
+						items.splice(i..j, found.into_iter().map(Item::Found));
+						break;
+					}
+				}
+			}
+		}
+		i += 1;
+	}
+
+	// Оставшиеся шаги переносятся из исходного текста как есть.
+	let chain_text = &input[chain.span.0.clone()];
+	let mut text = chain_text[..chain_text.find('{').expect("proof chain is written in braces") + 1].to_string();
+	text += "\n";
+	for item in &items {
+		match item {
+			Item::Written(number) => {
+				let step = &steps[*number];
+				let start = step.expr.span.0.start;
+				let end = step.discharged_by.span.0.end;
+				let end = end + input[end..].find(';').expect("step ends with `;`") + 1;
+				text += &format!("    {}\n", &input[start..end]);
+			},
+			Item::Found(step) => text += &step.to_lines("    "),
+		}
+	}
+	text += "}";
+
+	Ok(Minimized { span: chain.span.clone(), text, before: steps.len(), after: items.len() })
+}
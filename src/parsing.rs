@@ -1,4 +1,4 @@
-use std::ops::Range;
+use std::{fmt, ops::Range};
 
 use crate::{
 	binding::{AnyFunctionPattern, Binding},
@@ -6,11 +6,30 @@ use crate::{
 	utils::{apply::*, char_index::*, span::*},
 };
 
-#[derive(Debug)]
+/// `module.3l`, или `name.l` для леммы `have`, тогда `module_name` это имя леммы, а `position` не используется и равен нулю.
+#[derive(Clone, Debug)]
 pub struct FormulaPosition {
 	pub module_name: String,
 	pub position: usize,
 	pub left_to_right: bool,
+	/// Ссылка на лемму `have` из текущего доказательства.
+	pub lemma: bool,
+}
+
+impl FormulaPosition {
+	/// Ссылается ли на лемму `have` из текущего доказательства.
+	pub fn is_lemma(&self) -> bool { self.lemma }
+}
+
+impl fmt::Display for FormulaPosition {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let direction = if self.left_to_right { "l" } else { "r" };
+		if self.is_lemma() {
+			write!(f, "{}.{}", self.module_name, direction)
+		} else {
+			write!(f, "{}.{}{}", self.module_name, self.position, direction)
+		}
+	}
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -41,25 +60,184 @@ impl GetInnerExpression for ExpressionParsing {
 	fn get_inner_expression_mut(&mut self) -> &mut ExpressionMeta<Self> { &mut self.node }
 }
 
+/// Модуль, через который в доказательстве упоминаются условия теоремы: `hyp.1l`.
+pub const HYPOTHESIS_MODULE: &str = "hyp";
+
 #[derive(Debug)]
 pub struct Formula {
+	pub hypotheses: Vec<Spanned<Formula>>,
 	pub left: ExpressionParsing,
+	pub left_span: GlobalSpan,
 	pub right: ExpressionParsing,
+	pub right_span: GlobalSpan,
 }
 
-#[derive(Debug)]
+/// Где в выражении шага применяется формула.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StepPosition {
+	/// Каретка `.  ^^^` под выражением.
+	Caret(Range<CharIndex>),
+	/// `@0.1`: путь от корня по индексам аргументов. Так записывается позиция, когда выражение шага не записано.
+	Path(ExprPositionOwned),
+}
+
+#[derive(Clone, Debug)]
 pub struct ProofStep {
 	pub string: String,
-	pub expr: Spanned<ExpressionParsing>,
-	pub position: Spanned<Range<CharIndex>>,
-	pub used_formula: Spanned<FormulaPosition>,
+	/// `None`, если вместо выражения записано `_`, тогда шаг применяется к результату предыдущего шага.
+	pub expr: Spanned<Option<ExpressionParsing>>,
+	/// `None`, если вместо позиции записан `?` или у шага записано только выражение, тогда её находит `infer`.
+	pub position: Option<Spanned<StepPosition>>,
+	/// `None` так же, как и у `position`.
+	pub used_formula: Option<Spanned<FormulaPosition>>,
+	/// Где записаны каретка и формула. Если записано только выражение, это пустой спан сразу после его `;`.
+	pub annotation: GlobalSpan,
 	pub bindings: Spanned<Vec<Binding>>,
-	pub function_bindings: Spanned<Vec<(String, AnyFunctionPattern)>>,
+	pub function_bindings: Spanned<Vec<Spanned<(String, AnyFunctionPattern)>>>,
+	/// Формулы после `by`, которые доказывают обязательства используемой теоремы.
+	pub discharged_by: Spanned<Vec<Spanned<FormulaPosition>>>,
+}
+
+impl ProofStep {
+	/// Глобальные формулы, которые использует шаг, без условий текущей теоремы и лемм.
+	pub fn used_formulas(&self) -> impl Iterator<Item = &Spanned<FormulaPosition>> {
+		self.used_formula
+			.iter()
+			.chain(&self.discharged_by.inner)
+			.filter(|position| position.inner.module_name != HYPOTHESIS_MODULE && !position.inner.is_lemma())
+	}
+}
+
+/// С какой стороны формулы начинается цепочка шагов доказательства.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Side {
+	Left,
+	Right,
+}
+
+impl Side {
+	pub fn opposite(self) -> Side {
+		match self {
+			Side::Left => Side::Right,
+			Side::Right => Side::Left,
+		}
+	}
+}
+
+impl fmt::Display for Side {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Side::Left => write!(f, "left"),
+			Side::Right => write!(f, "right"),
+		}
+	}
+}
+
+/// Тактика, которая при проверке разворачивается в обычные шаги доказательства.
+#[derive(Clone, Debug)]
+pub enum Tactic {
+	/// `repeat sum.2l at @0.1`: применять формулу, пока она применяется. Без `at` каждый раз применяется в первом подходящем месте.
+	Repeat {
+		rule: Spanned<FormulaPosition>,
+		at: Option<Spanned<ExprPositionOwned>>,
+	},
+	/// `rewrite [sum.1l, sum.2l]`: применить формулы по очереди, каждую в первом подходящем месте.
+	Rewrite { rules: Vec<Spanned<FormulaPosition>> },
+	/// `simp using [sum, mul]`: применять формулы модулей в обе стороны, пока они уменьшают размер выражения.
+	Simp { modules: Vec<Spanned<String>> },
+}
+
+impl Tactic {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Tactic::Repeat { .. } => "repeat",
+			Tactic::Rewrite { .. } => "rewrite",
+			Tactic::Simp { .. } => "simp",
+		}
+	}
+
+	/// Формулы, которые явно указаны в тактике, без условий текущей теоремы и лемм.
+	pub fn used_formulas(&self) -> impl Iterator<Item = &Spanned<FormulaPosition>> {
+		let rules = match self {
+			Tactic::Repeat { rule, .. } => std::slice::from_ref(rule),
+			Tactic::Rewrite { rules } => &rules[..],
+			Tactic::Simp { .. } => &[],
+		};
+		rules.iter().filter(|position| position.inner.module_name != HYPOTHESIS_MODULE && !position.inner.is_lemma())
+	}
+
+	/// Модули, все формулы которых может использовать тактика.
+	pub fn used_modules(&self) -> &[Spanned<String>] {
+		match self {
+			Tactic::Simp { modules } => modules,
+			Tactic::Repeat { .. } | Tactic::Rewrite { .. } => &[],
+		}
+	}
+}
+
+/// `expr; tactic;`, где `expr` это выражение перед применением тактики.
+#[derive(Clone, Debug)]
+pub struct TacticStep {
+	pub expr: Spanned<ExpressionParsing>,
+	pub tactic: Spanned<Tactic>,
 }
 
 #[derive(Debug)]
-pub struct Proof {
+pub struct ProofChain {
+	pub side: Side,
 	pub steps: Vec<ProofStep>,
+	/// Леммы вместе с количеством шагов и тактик цепочки, которые записаны перед ними.
+	pub lemmas: Vec<(usize, Lemma)>,
+	/// Тактики вместе с количеством шагов цепочки, которые записаны перед ними.
+	pub tactics: Vec<(usize, TacticStep)>,
+}
+
+/// `have name: formula { proof };` внутри доказательства. Используется только следующими шагами этого доказательства как `name.l` или `name.r`.
+#[derive(Debug)]
+pub struct Lemma {
+	pub name: Spanned<String>,
+	pub formula: Spanned<Formula>,
+	pub proof: Spanned<Proof>,
+}
+
+/// Всё, что записано в шаге после выражения.
+struct StepAnnotation {
+	position: Option<Spanned<StepPosition>>,
+	used_formula: Option<Spanned<FormulaPosition>>,
+	span: GlobalSpan,
+	bindings: Spanned<Vec<Binding>>,
+	function_bindings: Spanned<Vec<Spanned<(String, AnyFunctionPattern)>>>,
+	discharged_by: Spanned<Vec<Spanned<FormulaPosition>>>,
+}
+
+enum ProofItem {
+	Step(ProofStep),
+	Lemma(Lemma),
+	Tactic(TacticStep),
+}
+
+/// Одна цепочка от левой или правой части формулы до другой части, либо две цепочки `left { ... } right { ... }`, которые должны прийти к одному выражению.
+#[derive(Debug)]
+pub struct Proof {
+	pub chains: Vec<Spanned<ProofChain>>,
+}
+
+impl Proof {
+	/// Все шаги всех цепочек, включая шаги доказательств лемм.
+	pub fn steps(&self) -> Box<dyn Iterator<Item = &ProofStep> + '_> {
+		Box::new(self.chains.iter().flat_map(|chain| {
+			chain.inner.steps.iter().chain(chain.inner.lemmas.iter().flat_map(|(_, lemma)| lemma.proof.inner.steps()))
+		}))
+	}
+
+	/// Все тактики всех цепочек, включая тактики доказательств лемм.
+	pub fn tactics(&self) -> Box<dyn Iterator<Item = &Tactic> + '_> {
+		Box::new(self.chains.iter().flat_map(|chain| {
+			chain.inner.tactics.iter().map(|(_, step)| &step.tactic.inner).chain(
+				chain.inner.lemmas.iter().flat_map(|(_, lemma)| lemma.proof.inner.tactics()),
+			)
+		}))
+	}
 }
 
 #[derive(Debug)]
@@ -78,6 +256,14 @@ pub struct NamedFormulas {
 #[derive(Debug)]
 pub struct Math(pub Vec<NamedFormulas>);
 
+impl Math {
+	/// Формула с номером `position` в модуле `module_name`, номера начинаются с единицы.
+	pub fn get(&self, module_name: &str, position: usize) -> Option<&FullFormula> {
+		let named = self.0.iter().find(|named| named.name == module_name)?;
+		named.formulas.get(position.checked_sub(1)?)
+	}
+}
+
 // TODO переделать на собственный алгоритм precedence!(), убрать костыль для парсинга неравенств и равенств
 peg::parser!(
 	pub grammar parser() for str {
@@ -115,32 +301,156 @@ peg::parser!(
 			}
 
 		pub rule proof() -> Proof
-			= "{" _ steps:(steps:proof_step() _ { steps })+ _ "}" {
-				Proof {
-					steps,
+			= left:proof_chain() _ right:proof_chain() {?
+				if left.inner.side == Side::Left && right.inner.side == Side::Right {
+					Ok(Proof { chains: vec![left, right] })
+				} else {
+					Err("`left { ... } right { ... }`")
+				}
+			}
+			/ chain:proof_chain() { Proof { chains: vec![chain] } }
+
+		rule proof_chain() -> Spanned<ProofChain>
+			= start:position!() side:("left" _ { Side::Left } / "right" _ { Side::Right })?
+			  "{" _ items:(item:proof_item() _ { item })+ _ "}" end:position!() {?
+				let mut steps = Vec::new();
+				let mut lemmas = Vec::new();
+				let mut tactics = Vec::new();
+				for item in items {
+					match item {
+						ProofItem::Step(step) => steps.push(step),
+						ProofItem::Lemma(lemma) => lemmas.push((steps.len() + tactics.len(), lemma)),
+						ProofItem::Tactic(tactic) => tactics.push((steps.len(), tactic)),
+					}
+				}
+				if steps.is_empty() && tactics.is_empty() {
+					Err("proof step")
+				} else {
+					Ok(Spanned {
+						span: GlobalSpan(start..end),
+						inner: ProofChain {
+							side: side.unwrap_or(Side::Left),
+							steps,
+							lemmas,
+							tactics,
+						},
+					})
 				}
 			}
 
-		pub rule proof_step() -> ProofStep
-			= start1:position!() expr:&expr_normalized() string:$(expr_normalized()) end1:position!() _ ";" _
-			  start2:position!() position:visual_positon() end2:position!() _
-			  start3:position!() used_formula:formula_position() end3:position!() _
-			  start4:position!() bindings:binding() ** (_ "," _ ) end4:position!() _
-			  start5:position!() function_bindings:function_binding() ** (_ "," _ ) end5:position!() _ ";" {
-				ProofStep {
-					string: string.to_string(),
+		rule proof_item() -> ProofItem
+			= lemma:lemma() { ProofItem::Lemma(lemma) }
+			/ tactic:tactic_step() { ProofItem::Tactic(tactic) }
+			/ step:proof_step() { ProofItem::Step(step) }
+
+		rule tactic_step() -> TacticStep
+			= start1:position!() expr:expr_normalized() end1:position!() _ ";" _
+			  start2:position!() tactic:tactic() end2:position!() _ ";" {
+				TacticStep {
 					expr: Spanned {
 						span: GlobalSpan(start1..end1),
 						inner: expr,
 					},
-					position: Spanned {
+					tactic: Spanned {
 						span: GlobalSpan(start2..end2),
-						inner: position,
+						inner: tactic,
 					},
-					used_formula: Spanned {
+				}
+			}
+
+		pub rule tactic() -> Tactic
+			= "repeat" _ formula:spanned_formula_position() at:(_ "at" _ at:spanned_expr_position() { at })? {
+				Tactic::Repeat { rule: formula, at }
+			}
+			/ "rewrite" _ "[" _ rules:spanned_formula_position() ++ (_ "," _) _ "]" { Tactic::Rewrite { rules } }
+			/ "simp" _ "using" _ "[" _ modules:spanned_identifier() ++ (_ "," _) _ "]" { Tactic::Simp { modules } }
+
+		rule spanned_expr_position() -> Spanned<ExprPositionOwned>
+			= start:position!() "@" path:integer() ** "." end:position!() {
+				Spanned {
+					span: GlobalSpan(start..end),
+					inner: ExprPositionOwned(path.into_iter().map(|x| x as usize).collect()),
+				}
+			}
+
+		rule spanned_identifier() -> Spanned<String>
+			= start:position!() name:identifier() end:position!() {
+				Spanned {
+					span: GlobalSpan(start..end),
+					inner: name,
+				}
+			}
+
+		rule lemma_name() -> String
+			= name:identifier() {?
+				if name == HYPOTHESIS_MODULE { Err("lemma name other than `hyp`, it is reserved for hypotheses") } else { Ok(name) }
+			}
+
+		rule lemma() -> Lemma
+			= "have" _ start1:position!() name:lemma_name() end1:position!() _ ":" _
+			  start2:position!() formula:formula() end2:position!() _
+			  start3:position!() proof:proof() end3:position!() _ ";" {
+				Lemma {
+					name: Spanned {
+						span: GlobalSpan(start1..end1),
+						inner: name,
+					},
+					formula: Spanned {
+						span: GlobalSpan(start2..end2),
+						inner: formula,
+					},
+					proof: Spanned {
 						span: GlobalSpan(start3..end3),
-						inner: used_formula,
+						inner: proof,
+					},
+				}
+			}
+
+		pub rule proof_step() -> ProofStep
+			= start1:position!() expr:&(computed() { None } / expr:expr_normalized() { Some(expr) })
+			  string:$(computed() / expr_normalized()) end1:position!() _ ";" end:position!()
+			  annotation:step_annotation()? {
+				let empty = || GlobalSpan(end..end);
+				let annotation = annotation.unwrap_or_else(|| StepAnnotation {
+					position: None,
+					used_formula: None,
+					span: empty(),
+					bindings: Spanned::new(Vec::new(), empty()),
+					function_bindings: Spanned::new(Vec::new(), empty()),
+					discharged_by: Spanned::new(Vec::new(), empty()),
+				});
+				ProofStep {
+					string: string.to_string(),
+					expr: Spanned {
+						span: GlobalSpan(start1..end1),
+						inner: expr,
 					},
+					position: annotation.position,
+					used_formula: annotation.used_formula,
+					annotation: annotation.span,
+					bindings: annotation.bindings,
+					function_bindings: annotation.function_bindings,
+					discharged_by: annotation.discharged_by,
+				}
+			}
+
+		/// `_` вместо выражения шага.
+		rule computed() = "_" !['a'..='z' | 'A'..='Z' | '_' | '0'..='9']
+
+		rule step_annotation() -> StepAnnotation
+			= _ start2:position!() position:(
+				position:visual_positon() { Some(StepPosition::Caret(position)) }
+				/ path:spanned_expr_position() { Some(StepPosition::Path(path.inner)) }
+				/ "?" !identifier() { None }
+			  ) end2:position!() _
+			  start3:position!() used_formula:(position:formula_position() { Some(position) } / "?" { None })? end3:position!() _
+			  start4:position!() bindings:binding() ** (_ "," _ ) end4:position!() _
+			  start5:position!() function_bindings:spanned_function_binding() ** (_ "," _ ) end5:position!() _
+			  start6:position!() discharged_by:("by" _ positions:spanned_formula_position() ++ (_ "," _) { positions })? end6:position!() _ ";" {
+				StepAnnotation {
+					position: position.map(|position| Spanned::new(position, GlobalSpan(start2..end2))),
+					used_formula: used_formula.flatten().map(|position| Spanned::new(position, GlobalSpan(start3..end3))),
+					span: GlobalSpan(start2..end3.max(end2)),
 					bindings: Spanned {
 						span: GlobalSpan(start4..end4),
 						inner: bindings,
@@ -149,14 +459,33 @@ peg::parser!(
 						span: GlobalSpan(start5..end5),
 						inner: function_bindings,
 					},
+					discharged_by: Spanned {
+						span: GlobalSpan(start6..end6),
+						inner: discharged_by.unwrap_or_default(),
+					},
 				}
 			}
 
 		pub rule formula() -> Formula
-			= left:expr_normalized() _ "<->" _ right:expr_normalized() {
+			= hypotheses:(hypotheses:hypothesis() ++ (_ "," _) _ "|-" _ { hypotheses })? formula:hypothesis() {
 				Formula {
-					left,
-					right
+					hypotheses: hypotheses.unwrap_or_default(),
+					..formula.inner
+				}
+			}
+
+		rule hypothesis() -> Spanned<Formula>
+			= start1:position!() left:expr_normalized() end1:position!() _ "<->" _
+			  start2:position!() right:expr_normalized() end2:position!() {
+				Spanned {
+					span: GlobalSpan(start1..end2),
+					inner: Formula {
+						hypotheses: Vec::new(),
+						left,
+						left_span: GlobalSpan(start1..end1),
+						right,
+						right_span: GlobalSpan(start2..end2),
+					},
 				}
 			}
 
@@ -169,16 +498,37 @@ peg::parser!(
 				.apply(|x| (name, x))
 			}
 
+		rule spanned_function_binding() -> Spanned<(String, AnyFunctionPattern)>
+			= start:position!() binding:function_binding() end:position!() {
+				Spanned {
+					span: GlobalSpan(start..end),
+					inner: binding,
+				}
+			}
+
 		pub rule binding() -> Binding
 			= name:identifier() _ ":=" _ to:expr_normalized() { Binding::new(name, clear_parsing_info(to)) }
 			// TODO add function binding, but it requires matching to many things
 
 		pub rule formula_position() -> FormulaPosition
-			= module_name:identifier() "." position:integer() left_to_right:("r" { false } / "l" { true }) {
-				FormulaPosition {
-					module_name,
-					position: position as usize,
-					left_to_right,
+			= module_name:identifier() "." position:integer()? left_to_right:("r" { false } / "l" { true }) {?
+				match position {
+					Some(0) => Err("formula number, they start from 1"),
+					None if module_name == HYPOTHESIS_MODULE => Err("hypothesis number, like `hyp.1l`"),
+					_ => Ok(FormulaPosition {
+						module_name,
+						position: position.unwrap_or(0) as usize,
+						left_to_right,
+						lemma: position.is_none(),
+					}),
+				}
+			}
+
+		rule spanned_formula_position() -> Spanned<FormulaPosition>
+			= start:position!() position:formula_position() end:position!() {
+				Spanned {
+					span: GlobalSpan(start..end),
+					inner: position,
 				}
 			}
 
@@ -197,7 +547,7 @@ peg::parser!(
 		rule or() -> ExpressionParsingGlobal
 			= start:position!()
 			  l:and()
-			  r:(_ z:$("|") _ r:and() end:position!()  { (z, r, end) })*
+			  r:(_ z:$("|" !"-") _ r:and() end:position!()  { (z, r, end) })*
 			  end:position!()
 			{
 				let mut result = l;
@@ -311,6 +661,12 @@ peg::parser!(
 					node: ExpressionMeta::Pattern { name }
 				}
 			}
+			/ start:position!() "?" name:identifier() end:position!() {
+				ExpressionParsingGlobal {
+					span: GlobalSpan(start..end),
+					node: ExpressionMeta::Pattern { name: format!("?{}", name) }
+				}
+			}
 
 		rule function() -> ExpressionParsingGlobal
 			= start:position!() name:identifier() "(" _ args:expr() ** (_ "," _) _ ")" end:position!() {
@@ -0,0 +1,139 @@
+use std::ops::Range;
+
+use crate::{
+	binding::{AnyFunctionPattern, Binding},
+	expr::*,
+	parsing::{parser, process_expression_parsing, FormulaPosition},
+	utils::{
+		char_index::{get_char_range, CharIndex},
+		joined_by::*,
+	},
+};
+
+/// Приоритеты операторов как в грамматике, от слабых к сильным.
+const OR: u8 = 1;
+const AND: u8 = 2;
+const EQUALITY: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const POWER: u8 = 6;
+const ATOM: u8 = 7;
+
+fn precedence(name: &str) -> Option<u8> {
+	match name {
+		"|" => Some(OR),
+		"&" => Some(AND),
+		"=" | "!=" | ">=" | "<=" | ">" | "<" => Some(EQUALITY),
+		"+" | "-" => Some(SUM),
+		"*" | "/" => Some(PRODUCT),
+		"^" => Some(POWER),
+		_ => None,
+	}
+}
+
+/// Пишет выражение так, чтобы в нём было `at_least` приоритета, иначе оборачивает в скобки.
+fn write(expr: &Expression, at_least: u8, result: &mut String) {
+	use ExpressionMeta::*;
+
+	let (own, text) = match &expr.0 {
+		NamedFunction { name, args } if args.len() == 2 && precedence(name).is_some() => {
+			let own = precedence(name).unwrap();
+			// Какой приоритет нужен аргументам: `-`, `/`, `&`, `|` и `+`, `*` левоассоциативны, `^` правоассоциативна, сравнения не ассоциативны.
+			let (left, right) = match own {
+				EQUALITY => (SUM, SUM),
+				POWER => (ATOM, POWER),
+				_ => (own, own + 1),
+			};
+			let mut text = String::new();
+			// `-a+b` не парсится, унарный минус слева от суммы пишется в скобках.
+			let left = match &args[0].0 {
+				NamedFunction { name, args } if own == SUM && name == "negative" && args.len() == 1 => PRODUCT,
+				_ => left,
+			};
+			write(&args[0], left, &mut text);
+			text += name;
+			write(&args[1], right, &mut text);
+			(own, text)
+		},
+		NamedFunction { name, args } if name == "negative" && args.len() == 1 => {
+			let mut text = String::from("-");
+			write(&args[0], PRODUCT, &mut text);
+			(SUM, text)
+		},
+		NamedFunction { name, args } => (ATOM, format!("{}({})", name, args.iter().map(to_string).joined_by(", "))),
+		AnyFunction { name, args } => (ATOM, format!("${}({})", name, args.iter().map(to_string).joined_by(", "))),
+		Pattern { name } => (ATOM, name.clone()),
+		NamedValue { name } => (ATOM, format!("${}", name)),
+		IntegerValue { value } if *value < 0 => (SUM, format!("-{}", -value)),
+		IntegerValue { value } => (ATOM, value.to_string()),
+	};
+	if own < at_least {
+		result.push('(');
+		result.push_str(&text);
+		result.push(')');
+	} else {
+		result.push_str(&text);
+	}
+}
+
+/// Выражение в том виде, в котором его пишут в `.fpl`: без лишних скобок. В отличие от `Display` результат парсится обратно в то же самое выражение.
+pub fn to_string(expr: &Expression) -> String {
+	let mut result = String::new();
+	write(expr, OR, &mut result);
+	result
+}
+
+/// Строка с кареткой под символами `range` выражения.
+pub fn caret_for(range: &Range<CharIndex>) -> String {
+	let before = if range.start.0 == 0 { String::new() } else { format!(".{}", " ".repeat(range.start.0 - 1)) };
+	format!("{}{}", before, "^".repeat(range.end.0 - range.start.0))
+}
+
+/// Строка с кареткой под подвыражением `position` в строке `string`, которая получена из `to_string`.
+pub fn caret(string: &str, position: &ExprPositionOwned) -> Option<String> {
+	let (_, positions) = process_expression_parsing(parser::expr_normalized(string).ok()?);
+	let range = positions.into_iter().find(|(x, _)| x == position)?.1.0;
+	Some(caret_for(&get_char_range(string, range)?))
+}
+
+/// Шаг доказательства без привязки к тексту: применить `rule` в позиции `position` к выражению `expr`.
+#[derive(Clone, Debug)]
+pub struct PlainStep {
+	pub expr: Expression,
+	pub position: ExprPositionOwned,
+	pub rule: FormulaPosition,
+	pub bindings: Vec<Binding>,
+	pub function_bindings: Vec<(String, AnyFunctionPattern)>,
+}
+
+impl PlainStep {
+	/// Две строки шага: выражение и каретка с формулой. `indent` добавляется в начало каждой строки.
+	pub fn to_lines(&self, indent: &str) -> String {
+		let string = to_string(&self.expr);
+		let caret = caret(&string, &self.position).expect("printed expression should be parsed back");
+		let mut result = format!("{}{};\n{}{} {}", indent, string, indent, caret, self.rule);
+		if !self.bindings.is_empty() {
+			let bindings =
+				self.bindings.iter().map(|binding| format!("{} := {}", binding.pattern_name, to_string(&binding.to_value)));
+			result += &format!(" {}", bindings.joined_by(", "));
+		}
+		if !self.function_bindings.is_empty() {
+			let bindings = self.function_bindings.iter().map(|(name, AnyFunctionPattern { pattern, variables })| {
+				format!("${}({}) := {}", name, variables.iter().joined_by(", "), to_string(pattern))
+			});
+			result += &format!(" {}", bindings.joined_by(", "));
+		}
+		result += ";\n";
+		result
+	}
+}
+
+/// Блок доказательства `{ ... }` из шагов одной цепочки от левой части формулы.
+pub fn proof_block(steps: &[PlainStep]) -> String {
+	let mut result = String::from("{\n");
+	for step in steps {
+		result += &step.to_lines("    ");
+	}
+	result += "}";
+	result
+}
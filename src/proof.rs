@@ -1,16 +1,39 @@
 use std::{
 	borrow::Borrow,
+	cell::RefCell,
 	collections::{BTreeMap, BTreeSet},
+	fmt,
+	sync::atomic::{AtomicUsize, Ordering},
 };
 
-use petgraph::{graph::NodeIndex, Graph};
+use petgraph::{
+	graph::{EdgeIndex, NodeIndex},
+	visit::EdgeRef,
+	Graph,
+};
 use thiserror::Error;
 
 use crate::{
-	binding::{apply_bindings, find_bindings, BindingStorage, Formula, FormulaError, ManualAnyFunctionBinding},
-	expr::{ExprPositionOwned, Expression, ExpressionExtension, ExpressionMeta, PositionError},
-	parsing::{clear_parsing_info, process_expression_parsing, Math, NamedFormulas, Proof, ProofStep},
-	utils::{char_index::get_char_range, id::*, span::*},
+	binding::{
+		apply_bindings, find_bindings, find_bindings_at, AnyFunctionPattern, ApplyError, BindingStorage, Formula,
+		FormulaError, Hypothesis, ManualAnyFunctionBinding, MatchError, MatchErrorKind,
+	},
+	expr::{
+		AnyFunctionNames, ExprPosition, ExprPositionOwned, Expression, ExpressionExtension, ExpressionMeta,
+		PositionError,
+	},
+	parsing::{
+		clear_parsing_info, process_expression_parsing, Lemma, Math, NamedFormulas, Proof, ProofChain, ProofStep,
+		Side, StepPosition, Tactic, HYPOTHESIS_MODULE,
+	},
+	tactic::{self, TacticError},
+	unify::unify_metavariables,
+	utils::{
+		apply::*,
+		char_index::get_char_range,
+		joined_by::*,
+		span::*,
+	},
 };
 
 #[derive(Default, Ord, PartialOrd, Debug, Clone, Eq, PartialEq, Hash)]
@@ -19,18 +42,49 @@ pub struct FormulaPosition {
 	pub position: usize,
 }
 
+impl FormulaPosition {
+	/// Может ли тактика с модулями в доказательстве формулы `self` использовать формулу `used`. Из своего модуля берутся только формулы, записанные выше, иначе формула ниже, которая ссылается на эту, давала бы цикл. Из других модулей берутся все формулы.
+	pub fn can_use_from_module(&self, used: &FormulaPosition) -> bool {
+		used.module_name != self.module_name || used.position < self.position
+	}
+}
+
+impl fmt::Display for FormulaPosition {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}.{}", self.module_name, self.position) }
+}
+
 #[derive(Debug, Error)]
 pub enum ReadMathError {
 	#[error("wrong number, should be {should_be}")]
 	WrongNumberInStart { should_be: usize },
 	#[error("{0}")]
 	FormulaError(FormulaError),
+	#[error("module name `{}` is reserved for hypotheses of theorem", HYPOTHESIS_MODULE)]
+	ReservedModuleName,
+}
+
+/// Условия теоремы без информации о спанах.
+pub(crate) fn hypotheses(formula: &crate::parsing::Formula) -> Vec<Hypothesis> {
+	formula
+		.hypotheses
+		.iter()
+		.map(|hypothesis| Hypothesis {
+			left: clear_parsing_info(hypothesis.inner.left.clone()),
+			right: clear_parsing_info(hypothesis.inner.right.clone()),
+		})
+		.collect()
 }
 
 pub fn read_math(math: &Math) -> Result<BTreeMap<FormulaPosition, Formula>, Vec<Spanned<ReadMathError>>> {
 	let mut errors = Vec::new();
 	let mut result = BTreeMap::new();
 	for NamedFormulas { name, formulas } in &math.0 {
+		if name == HYPOTHESIS_MODULE {
+			if let Some(formula) = formulas.first() {
+				errors.push(Spanned::new(ReadMathError::ReservedModuleName, formula.position.span.clone()));
+			}
+			continue;
+		}
 		for (index, formula) in formulas.iter().enumerate() {
 			if index + 1 != formula.position.inner as usize {
 				errors.push(Spanned::new(
@@ -45,7 +99,10 @@ pub fn read_math(math: &Math) -> Result<BTreeMap<FormulaPosition, Formula>, Vec<
 			let formula = match Formula::new(
 				clear_parsing_info(formula.formula.inner.left.clone()),
 				clear_parsing_info(formula.formula.inner.right.clone()),
-			) {
+			)
+			.and_then(|x| x.with_hypotheses(hypotheses(&formula.formula.inner)))
+			.and_then(Formula::reject_metavariables)
+			{
 				Ok(x) => x,
 				Err(x) => {
 					errors.push(Spanned::new(ReadMathError::FormulaError(x), formula.formula.span.clone()));
@@ -56,180 +113,1089 @@ pub fn read_math(math: &Math) -> Result<BTreeMap<FormulaPosition, Formula>, Vec<
 			result.insert(position, formula);
 		}
 	}
-	if errors.len() == 0 { Ok(result) } else { Err(errors) }
+	if errors.is_empty() { Ok(result) } else { Err(errors) }
+}
+
+/// Ссылка из доказательства одной формулы на другую формулу.
+#[derive(Clone, Debug)]
+pub struct Dependency {
+	/// Место в доказательстве, где используется формула.
+	pub used_at: GlobalSpan,
 }
 
-pub fn proofs_has_cycles(math: &Math) -> Result<(), &'static str> {
-	let mut id_generator = IdGenerator::default();
-	let mut edges = vec![];
+/// Граф зависимостей: ребро `a -> b` означает, что в доказательстве `a` используется `b`. На каждое использование своё ребро.
+pub fn dependency_graph(math: &Math) -> Graph<FormulaPosition, Dependency> {
+	let mut graph = Graph::new();
+	let mut nodes = BTreeMap::new();
+	let mut node = |graph: &mut Graph<FormulaPosition, Dependency>, position: FormulaPosition| {
+		*nodes.entry(position.clone()).or_insert_with(|| graph.add_node(position))
+	};
+
+	for NamedFormulas { name, formulas } in &math.0 {
+		for (index, _) in formulas.iter().enumerate() {
+			node(&mut graph, FormulaPosition { module_name: name.clone(), position: index + 1 });
+		}
+	}
 	for NamedFormulas { name, formulas } in &math.0 {
 		for (index, formula) in formulas.iter().enumerate() {
-			let current_position = NodeIndex::new(
-				id_generator.get_or_add_id(FormulaPosition { module_name: name.clone(), position: index + 1 }) as usize,
-			);
+			let current_position = node(&mut graph, FormulaPosition { module_name: name.clone(), position: index + 1 });
 			if let Some(proof) = &formula.proof {
-				for ProofStep { used_formula, .. } in &proof.inner.steps {
-					let used_position = NodeIndex::new(id_generator.get_or_add_id(FormulaPosition {
+				let used_formulas = proof
+					.inner
+					.steps()
+					.flat_map(ProofStep::used_formulas)
+					.chain(proof.inner.tactics().flat_map(Tactic::used_formulas));
+				for used_formula in used_formulas {
+					let used_position = node(&mut graph, FormulaPosition {
 						module_name: used_formula.inner.module_name.clone(),
 						position: used_formula.inner.position,
-					}) as usize);
-					edges.push((current_position, used_position));
+					});
+					graph.add_edge(current_position, used_position, Dependency { used_at: used_formula.span.clone() });
+				}
+
+				// Тактика с модулями может использовать формулы модуля, см. `FormulaPosition::can_use_from_module`.
+				for module in proof.inner.tactics().flat_map(Tactic::used_modules) {
+					for NamedFormulas { name: used_name, formulas: used_formulas } in &math.0 {
+						if *used_name != module.inner {
+							continue;
+						}
+						for used_index in 0..used_formulas.len() {
+							let used_position =
+								FormulaPosition { module_name: used_name.clone(), position: used_index + 1 };
+							if graph[current_position].can_use_from_module(&used_position) {
+								let used_position = node(&mut graph, used_position);
+								graph.add_edge(current_position, used_position, Dependency { used_at: module.span.clone() });
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+	graph
+}
+
+#[derive(Debug, Error)]
+pub enum DependencyError {
+	#[error("proofs have a cycle: {}", cycle.iter().chain(cycle.first()).joined_by(" -> "))]
+	Cycle { cycle: Vec<FormulaPosition> },
+	#[error("formula `{0}` is not found")]
+	FormulaNotFound(FormulaPosition),
+}
+
+/// Проверяет, что доказательства не ссылаются на несуществующие формулы и не образуют циклов. Для каждого цикла возвращает формулы, из которых он состоит, и места, где они ссылаются друг на друга.
+pub fn proofs_has_cycles(math: &Math) -> Result<(), Vec<Diagnostic<DependencyError>>> {
+	let graph = dependency_graph(math);
+	let mut errors = Vec::new();
+
+	let defined: BTreeSet<FormulaPosition> = math
+		.0
+		.iter()
+		.flat_map(|NamedFormulas { name, formulas }| {
+			(1..=formulas.len()).map(move |position| FormulaPosition { module_name: name.clone(), position })
+		})
+		.collect();
+	for edge in graph.edge_references() {
+		let used = &graph[edge.target()];
+		if !defined.contains(used) {
+			errors.push(Diagnostic::new(Spanned::new(
+				DependencyError::FormulaNotFound(used.clone()),
+				edge.weight().used_at.clone(),
+			)));
+		}
+	}
+
+	for component in petgraph::algo::tarjan_scc(&graph) {
+		let start = component[0];
+		let is_cycle = component.len() > 1 || graph.find_edge(start, start).is_some();
+		if !is_cycle {
+			continue;
+		}
+
+		// Ищем в компоненте кратчайший путь из `start` обратно в `start`.
+		let component: BTreeSet<NodeIndex> = component.into_iter().collect();
+		let mut parent: BTreeMap<NodeIndex, EdgeIndex> = BTreeMap::new();
+		let mut queue = std::collections::VecDeque::from(vec![start]);
+		let mut closing = None;
+		'search: while let Some(node) = queue.pop_front() {
+			for edge in graph.edges(node) {
+				let target = edge.target();
+				if target == start {
+					closing = Some(edge.id());
+					break 'search;
+				}
+				if component.contains(&target) && !parent.contains_key(&target) {
+					parent.insert(target, edge.id());
+					queue.push_back(target);
 				}
 			}
 		}
+
+		let mut edges = vec![closing.expect("strongly connected component always has a cycle")];
+		let mut node = graph.edge_endpoints(edges[0]).unwrap().0;
+		while node != start {
+			let edge = parent[&node];
+			edges.push(edge);
+			node = graph.edge_endpoints(edge).unwrap().0;
+		}
+		edges.reverse();
+
+		let cycle = edges.iter().map(|edge| graph[graph.edge_endpoints(*edge).unwrap().0].clone()).collect();
+		let reference = |edge: EdgeIndex| {
+			let (from, to) = graph.edge_endpoints(edge).unwrap();
+			Spanned::new(format!("`{}` uses `{}` here", graph[from], graph[to]), graph[edge].used_at.clone())
+		};
+		errors.push(Diagnostic {
+			error: Spanned::new(DependencyError::Cycle { cycle }, graph[edges[0]].used_at.clone()),
+			notes: edges.iter().skip(1).map(|edge| reference(*edge)).collect(),
+		});
 	}
 
-	let graph = Graph::<(), ()>::from_edges(&edges);
-	if petgraph::algo::is_cyclic_directed(&graph) { Err("proof has cycles") } else { Ok(()) }
+	if errors.is_empty() { Ok(()) } else { Err(errors) }
 }
 
 #[derive(Debug, Error)]
 pub enum ProofError {
 	#[error("position is not found")]
 	PositionNotFound,
+	#[error("position and formula of this step are not inferred")]
+	NotInferred,
+	#[error("`_` is the result of previous step, but it is broken")]
+	NothingToCompute,
+	#[error("caret can't point into `_`, write position as `@0.1`")]
+	CaretWithoutExpression,
 	#[error("result of this step is not equal to expected, actual is {actual}")]
 	StepWrong { actual: Expression },
-	#[error("result of latest step is not equal to right side of formula, actual is {actual}")]
-	LatestStepWrong { actual: Expression },
+	#[error("result of latest step is not equal to {side} side of formula, actual is {actual}")]
+	LatestStepWrong { side: Side, actual: Expression },
+	#[error("left and right chains end at different expressions: {left} and {right}")]
+	ChainsDontMeet { left: Expression, right: Expression },
 	#[error("formula by this name is not found")]
 	FormulaNotFound,
-	#[error("not all bindings provided")]
-	NotAllBindingsProvided, // TODO add which bindings needed
-	#[error("not all function bindings provided")]
-	NotAllFunctionBindingsProvided,
+	#[error("bindings for [{}] are not needed, these patterns are already known from formula{}", extra.iter().joined_by(", "), if functions.is_empty() { String::new() } else { format!(", and [{}] are functions, bind them as `$f(x) := ...`", signatures(functions)) })]
+	ExtraBindingsProvided {
+		extra: Vec<String>,
+		/// Функции формулы, которым биндинг записан как паттерну.
+		functions: Vec<AnyFunctionNames>,
+	},
+	#[error("wrong function bindings: missing [{}], superfluous [{}]", signatures(missing), signatures(extra))]
+	WrongFunctionBindings {
+		missing: Vec<AnyFunctionNames>,
+		extra: Vec<AnyFunctionNames>,
+	},
 	#[error("internal error about getting part of formula, in {position:?}, on {error_in:?}")]
 	InternalError {
 		position: ExprPositionOwned,
 		error_in: PositionError,
 	},
-	#[error("cannot match formula with this equation")]
-	CannotFindBindings,
+	#[error("cannot match formula with this equation: {0}")]
+	CannotFindBindings(MatchError),
+	#[error("cannot apply function bindings: {0}")]
+	CannotApplyBindings(ApplyError),
+	#[error("{0}")]
+	FormulaError(FormulaError),
+	#[error("lemma with this name is already defined")]
+	LemmaRedefined,
+	#[error("pattern `{name}` is bound twice")]
+	BindingRedefined { name: String },
+	#[error("function `${name}` is bound twice")]
+	FunctionBindingRedefined {
+		name: String,
+		/// Первый биндинг этой функции.
+		first: GlobalSpan,
+	},
+	#[error("hypothesis `{obligation}` of this formula is not proved, it should be a hypothesis of current theorem or be proved by formula after `by`")]
+	ObligationNotDischarged { obligation: Hypothesis },
+	#[error("{0}")]
+	TacticFailed(TacticError),
 }
 
-pub fn is_proof_correct(
-	formula: &crate::parsing::Formula,
-	proof: &Spanned<Proof>,
-	global_formulas: &BTreeMap<FormulaPosition, Formula>,
-) -> Result<(), Spanned<ProofError>> {
-	let mut current = clear_parsing_info(formula.left.clone());
-
-	for ProofStep { string, expr, position, used_formula, bindings, function_bindings } in &proof.inner.steps {
-		let expr_parsing = &expr.inner;
-		let expr_span = expr.span.clone();
-		let (mut expr, position) = {
-			let (expr, positions) = process_expression_parsing(expr.inner.clone());
-			let position = positions
-				.iter()
-				.find(|(_, range)| {
-					get_char_range(&string, range.0.clone())
-						.map(|x| x == position.inner)
-						.unwrap_or(false)
-				})
-				.ok_or(Spanned::new(ProofError::PositionNotFound, position.span.clone()))?
-				.0
-				.clone();
-			(expr, position)
+/// Функции в том виде, в котором записываются их биндинги, чтобы было видно число аргументов: `$f(_), $g(_, _)`.
+fn signatures(functions: &[AnyFunctionNames]) -> String {
+	functions
+		.iter()
+		.map(|function| format!("${}({})", function.name, vec!["_"; function.arguments_count].join(", ")))
+		.joined_by(", ")
+		.to_string()
+}
+
+/// Имя метапеременной для паттерна `name`, которое ещё не встречается в `taken_names`.
+fn fresh_metavariable(name: &str, taken_names: &BTreeSet<String>) -> String {
+	let mut result = format!("?{}", name);
+	let mut index = 1;
+	while taken_names.contains(&result) {
+		result = format!("?{}{}", name, index);
+		index += 1;
+	}
+	result
+}
+
+/// Ошибка в шаге доказательства вместе с пояснением, от какого предыдущего шага этот шаг зависит.
+pub type ProofStepError = Diagnostic<ProofError>;
+
+/// Формула шага. Её нет только у шагов, которые `infer` ещё не обработал.
+fn step_rule(step: &ProofStep) -> Result<&Spanned<crate::parsing::FormulaPosition>, Spanned<ProofError>> {
+	step.used_formula.as_ref().ok_or_else(|| Spanned::new(ProofError::NotInferred, step.expr.span.clone()))
+}
+
+/// Находит выражение шага и позицию в нём по каретке или по пути `@0.1`. Вместо `_` берётся `current`, результат предыдущего шага.
+fn parse_proof_step(
+	step: &ProofStep,
+	current: Option<&Expression>,
+) -> Result<(Expression, ExprPositionOwned), Spanned<ProofError>> {
+	let caret =
+		step.position.as_ref().ok_or_else(|| Spanned::new(ProofError::NotInferred, step.expr.span.clone()))?;
+	let (expr, positions) = match &step.expr.inner {
+		Some(written) => process_expression_parsing(written.clone()),
+		None => {
+			let current = current.ok_or_else(|| Spanned::new(ProofError::NothingToCompute, step.expr.span.clone()))?;
+			(current.clone(), Vec::new())
+		},
+	};
+	let position = match &caret.inner {
+		StepPosition::Caret(_) if step.expr.inner.is_none() => {
+			return Err(Spanned::new(ProofError::CaretWithoutExpression, caret.span.clone()));
+		},
+		StepPosition::Caret(range) => positions
+			.into_iter()
+			.find(|(_, x)| get_char_range(&step.string, x.0.clone()).map(|x| x == *range).unwrap_or(false))
+			.map(|(position, _)| position),
+		StepPosition::Path(path) => expr.get(path).ok().map(|_| path.clone()),
+	};
+	let position = position.ok_or_else(|| Spanned::new(ProofError::PositionNotFound, caret.span.clone()))?;
+	Ok((expr, position))
+}
+
+/// Всё, на что могут ссылаться шаги доказательства: глобальные формулы, условия текущей теоремы и леммы `have`, записанные выше.
+pub(crate) struct ProofContext<'a> {
+	pub(crate) global_formulas: &'a BTreeMap<FormulaPosition, Formula>,
+	/// Доказываемая формула, её нельзя использовать в тактиках с модулями.
+	pub(crate) current: FormulaPosition,
+	pub(crate) hypotheses: Vec<Hypothesis>,
+	pub(crate) lemmas: BTreeMap<String, Formula>,
+	/// Выражения, которые посчитаны вместо `_`, вместе с местом `_`.
+	pub(crate) computed: Vec<(GlobalSpan, Expression)>,
+	/// Формулы шагов, в которые развернулись тактики. Если тактика не развернулась, то формулы, явно указанные в ней.
+	pub(crate) tactic_rules: Vec<crate::parsing::FormulaPosition>,
+	/// Метапеременные, которые создала проверка. Только они уточняются унификацией, а `?x`, записанный в шаге без такой метапеременной, это обычный паттерн.
+	pub(crate) metavariables: RefCell<BTreeSet<String>>,
+}
+
+impl ProofContext<'_> {
+	fn formula(&self, used_formula: &Spanned<crate::parsing::FormulaPosition>) -> Result<&Formula, Spanned<ProofError>> {
+		let crate::parsing::FormulaPosition { module_name, position, .. } = &used_formula.inner;
+		let result = if used_formula.inner.is_lemma() {
+			self.lemmas.get(module_name)
+		} else {
+			self.global_formulas.get(&FormulaPosition { module_name: module_name.clone(), position: *position })
 		};
+		result.ok_or_else(|| Spanned::new(ProofError::FormulaNotFound, used_formula.span.clone()))
+	}
+}
+
+/// Находит формулу шага, разворачивает её в нужную сторону и проверяет, что биндинги заданы ровно для тех паттернов и функций, для которых нужно.
+fn get_step_formula(step: &ProofStep, context: &ProofContext) -> Result<Formula, Spanned<ProofError>> {
+	let ProofStep { bindings, function_bindings, .. } = step;
+	let used_formula = step_rule(step)?;
 
-		if expr != current {
-			return Err(Spanned::new(ProofError::StepWrong { actual: current }, expr_span));
+	let mut result = context.formula(used_formula)?.clone();
+	if !used_formula.inner.left_to_right {
+		std::mem::swap(&mut result.left, &mut result.right);
+	}
+
+	let mut sorted_used_names = BTreeSet::new();
+	for binding in &bindings.inner {
+		if !sorted_used_names.insert(binding.pattern_name.clone()) {
+			let name = binding.pattern_name.clone();
+			return Err(Spanned::new(ProofError::BindingRedefined { name }, bindings.span.clone()));
 		}
+	}
+	let mut function_spans = BTreeMap::new();
+	for binding in &function_bindings.inner {
+		if let Some(first) = function_spans.insert(&binding.inner.0, &binding.span) {
+			return Err(Spanned::new(
+				ProofError::FunctionBindingRedefined { name: binding.inner.0.clone(), first: first.clone() },
+				binding.span.clone(),
+			));
+		}
+	}
 
-		let formula = {
-			let formula_position = FormulaPosition {
-				module_name: used_formula.inner.module_name.clone(),
-				position: used_formula.inner.position,
-			};
-			let mut result = global_formulas
-				.get(&formula_position)
-				.ok_or(Spanned::new(ProofError::FormulaNotFound, used_formula.span.clone()))?
-				.clone();
-			if !used_formula.inner.left_to_right {
-				std::mem::swap(&mut result.left, &mut result.right);
-			}
+	let sorted_unknown_names: BTreeSet<String> = result.left.unknown_patterns_names.iter().cloned().collect();
+	let extra: Vec<String> = sorted_used_names.difference(&sorted_unknown_names).cloned().collect();
+	if !extra.is_empty() {
+		let functions = result
+			.left
+			.anyfunction_names
+			.iter()
+			.filter(|(name, _)| extra.contains(name))
+			.map(|(name, arguments_count)| AnyFunctionNames { name: name.clone(), arguments_count: *arguments_count })
+			.collect();
+		return Err(Spanned::new(ProofError::ExtraBindingsProvided { extra, functions }, bindings.span.clone()));
+	}
+
+	let sorted_unknown_anyfunctions: BTreeSet<AnyFunctionNames> = result
+		.left
+		.anyfunction_names
+		.iter()
+		.map(|(name, arguments_count)| AnyFunctionNames { name: name.clone(), arguments_count: *arguments_count })
+		.collect();
+	let sorted_function_bindings: BTreeSet<AnyFunctionNames> = function_bindings
+		.inner
+		.iter()
+		.map(|binding| AnyFunctionNames {
+			name: binding.inner.0.clone(),
+			arguments_count: binding.inner.1.variables.len(),
+		})
+		.collect();
+	if sorted_unknown_anyfunctions != sorted_function_bindings {
+		return Err(Spanned::new(
+			ProofError::WrongFunctionBindings {
+				missing: sorted_unknown_anyfunctions.difference(&sorted_function_bindings).cloned().collect(),
+				extra: sorted_function_bindings.difference(&sorted_unknown_anyfunctions).cloned().collect(),
+			},
+			function_bindings.span.clone(),
+		));
+	}
+
+	Ok(result)
+}
 
-			let sorted_unknown_names: BTreeSet<String> = result.left.unknown_patterns_names.iter().cloned().collect();
-			let sorted_used_names: BTreeSet<String> = bindings.inner.iter().map(|b| b.pattern_name.clone()).collect();
-			if sorted_unknown_names != sorted_used_names {
-				return Err(Spanned::new(ProofError::NotAllBindingsProvided, bindings.span.clone()));
+/// Варианты единственного недостающего биндинга функции с одним аргументом для шага `step`, применённого к `expr` в позиции `position`. Функция ищется как подвыражение, в котором одно или все одинаковые вхождения аргумента заменены на переменную. Если не хватает другого числа функций или у функции не один аргумент, вариантов нет.
+fn function_binding_candidates(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	step: &ProofStep,
+	context: &ProofContext,
+) -> Vec<(String, AnyFunctionPattern)> {
+	let missing = match get_step_formula(step, context).map_err(|x| x.inner) {
+		Err(ProofError::WrongFunctionBindings { missing, extra }) if missing.len() == 1 && extra.is_empty() => {
+			missing.into_iter().next().unwrap()
+		},
+		_ => return Vec::new(),
+	};
+	if missing.arguments_count != 1 {
+		return Vec::new();
+	}
+	function_binding_candidates_for(expr, position, step, &missing.name, context).unwrap_or_default()
+}
+
+/// Варианты биндинга функции `function`, см. `function_binding_candidates`.
+fn function_binding_candidates_for(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	step: &ProofStep,
+	function: &str,
+	context: &ProofContext,
+) -> Option<Vec<(String, AnyFunctionPattern)>> {
+	let used_formula = step.used_formula.as_ref()?;
+	let mut formula = context.formula(used_formula).ok()?.clone();
+	if !used_formula.inner.left_to_right {
+		std::mem::swap(&mut formula.left, &mut formula.right);
+	}
+
+	// Где функция стоит в левой части формулы и что у неё в аргументе.
+	let mut found = None;
+	formula.left.pattern.travel_positions(|part, part_position| {
+		if let ExpressionMeta::AnyFunction { name, args } = &part.0 {
+			if name == function && found.is_none() {
+				found = Some((ExprPositionOwned(part_position.0.to_vec()), args[0].clone()));
 			}
+		}
+	});
+	let (function_position, argument) = found?;
+	let inner = expr.get(position).ok()?.get(&function_position).ok()?;
+
+	let mut matched = Vec::new();
+	inner.travel_positions(|part, part_position| {
+		let mut bindings = BindingStorage::default();
+		let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::new());
+		if find_bindings(part.clone(), &argument, &mut bindings, &mut any_function_bindings).is_ok() {
+			matched.push((ExprPositionOwned(part_position.0.to_vec()), part.clone()));
+		}
+	});
+
+	let mut candidates: Vec<Vec<ExprPositionOwned>> = matched.iter().map(|(position, _)| vec![position.clone()]).collect();
+	for (_, value) in &matched {
+		let same: Vec<_> =
+			matched.iter().filter(|(_, other)| other == value).map(|(position, _)| position.clone()).collect();
+		if same.len() > 1 && !candidates.contains(&same) {
+			candidates.push(same);
+		}
+	}
+
+	let taken_names = inner.get_pattern_names();
+	let variable = ["x", "y", "z", "t", "u", "v", "w"]
+		.iter()
+		.map(|name| name.to_string())
+		.chain((1..).map(|index| format!("x{}", index)))
+		.find(|name| !taken_names.contains(name))
+		.unwrap();
 
-			let sorted_unknown_anyfunctions: BTreeSet<(String, usize)> =
-				result.left.anyfunction_names.iter().cloned().collect();
-			let sorted_function_bindings: BTreeSet<(String, usize)> = function_bindings
-				.inner
-				.iter()
-				.map(|(name, pattern)| (name.clone(), pattern.variables.len()))
-				.collect();
-			if sorted_unknown_anyfunctions != sorted_function_bindings {
-				return Err(Spanned::new(ProofError::NotAllFunctionBindingsProvided, function_bindings.span.clone()));
+	let result = candidates
+		.into_iter()
+		.filter_map(|positions| {
+			let mut pattern = inner.clone();
+			for position in positions {
+				*pattern.get_mut(&position).ok()? = ExpressionMeta::Pattern { name: variable.clone() }.apply(Expression);
 			}
+			Some((function.to_string(), AnyFunctionPattern { pattern, variables: vec![variable.clone()] }))
+		})
+		.collect();
+	Some(result)
+}
+
+/// Шаг `step` с добавленным биндингом функции.
+fn with_function_binding(step: &ProofStep, binding: (String, AnyFunctionPattern)) -> ProofStep {
+	let mut step = step.clone();
+	step.function_bindings.inner.push(Spanned::new(binding, step.function_bindings.span.clone()));
+	step
+}
+
+/// Пытается подобрать единственный недостающий биндинг функции с одним аргументом так, чтобы результат шага совпал с выражением `next`, см. `function_binding_candidates`.
+fn infer_function_binding(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	step: &ProofStep,
+	next: &Expression,
+	context: &ProofContext,
+) -> Option<(String, AnyFunctionPattern)> {
+	function_binding_candidates(expr, position, step, context).into_iter().find(|binding| {
+		let step = with_function_binding(step, binding.clone());
+		apply_proof_step(expr.clone(), position, &step, context)
+			.is_ok_and(|result| unify_metavariables(&result, next, &context.metavariables.borrow()).is_ok())
+	})
+}
 
-			result
+/// Проверяет, что обязательство `obligation` тривиально, совпадает с условием текущей теоремы или является частным случаем одной из формул `discharged_by`. Формулы с условиями для этого не используются.
+fn is_obligation_discharged(
+	obligation: &Hypothesis,
+	discharged_by: &[Spanned<crate::parsing::FormulaPosition>],
+	context: &ProofContext,
+) -> Result<bool, Spanned<ProofError>> {
+	let swapped = Hypothesis { left: obligation.right.clone(), right: obligation.left.clone() };
+	if obligation.left == obligation.right || context.hypotheses.iter().any(|x| *x == *obligation || *x == swapped) {
+		return Ok(true);
+	}
+
+	for used_formula in discharged_by {
+		let formula = context.formula(used_formula)?;
+		if !formula.hypotheses.is_empty() {
+			continue;
+		}
+		let (left, right) = if used_formula.inner.left_to_right {
+			(&formula.left.pattern, &formula.right.pattern)
+		} else {
+			(&formula.right.pattern, &formula.left.pattern)
 		};
 
-		let mut current_expr_part = Expression(ExpressionMeta::IntegerValue { value: 0 });
-		let current_expr = expr.get_mut(position.borrow()).map_err(|pos| {
-			Spanned::new(
-				ProofError::InternalError { position: position.clone(), error_in: pos },
-				expr_parsing
-					.get(position.cut_to_error(pos))
-					.unwrap()
-					.span
-					.clone()
-					.globalize_span(expr_span.0.start),
-			)
-		})?;
-		std::mem::swap(&mut current_expr_part, current_expr);
+		let mut bindings = BindingStorage::default();
+		let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::new());
+		if find_bindings(obligation.left.clone(), left, &mut bindings, &mut any_function_bindings).is_ok()
+			&& find_bindings(obligation.right.clone(), right, &mut bindings, &mut any_function_bindings).is_ok()
+		{
+			return Ok(true);
+		}
+	}
 
-		let mut bindings = {
-			let mut result = BindingStorage::default();
-			for binding in &bindings.inner {
-				result.add(binding.clone());
-			}
-			result
+	Ok(false)
+}
+
+/// Применяет формулу шага к выражению `expr` в позиции `position` и возвращает результат.
+pub(crate) fn apply_proof_step(
+	mut expr: Expression,
+	position: &ExprPositionOwned,
+	step: &ProofStep,
+	context: &ProofContext,
+) -> Result<Expression, Spanned<ProofError>> {
+	let ProofStep { expr: expr_parsing, bindings, function_bindings, .. } = step;
+	let used_formula = step_rule(step)?;
+	let expr_span = expr_parsing.span.clone();
+
+	// Подвыражение в записанном тексте; если его там нет, то ближайший существующий родитель, а если выражение не записано, то весь `_`.
+	let part_span = |position: &ExprPosition| match &expr_parsing.inner {
+		Some(written) => written
+			.get(position)
+			.or_else(|pos| written.get(position.cut_to_error(pos)))
+			.unwrap_or(written)
+			.span
+			.clone()
+			.globalize_span(expr_span.0.start),
+		None => expr_span.clone(),
+	};
+
+	let mut taken_names = expr.get_pattern_names();
+
+	let mut current_expr_part = Expression(ExpressionMeta::IntegerValue { value: 0 });
+	let current_expr = expr.get_mut(position.borrow()).map_err(|pos| {
+		Spanned::new(ProofError::InternalError { position: position.clone(), error_in: pos }, part_span(position))
+	})?;
+	std::mem::swap(&mut current_expr_part, current_expr);
+
+	// Показываем подвыражение, на котором сопоставление сломалось.
+	let match_error = |error: MatchError| {
+		let span = part_span(&error.position);
+		Spanned::new(ProofError::CannotFindBindings(error), span)
+	};
+
+	// Условие текущей теоремы применяется только к точно такому же выражению.
+	if used_formula.inner.module_name == HYPOTHESIS_MODULE {
+		let hypothesis = used_formula
+			.inner
+			.position
+			.checked_sub(1)
+			.and_then(|index| context.hypotheses.get(index))
+			.ok_or_else(|| Spanned::new(ProofError::FormulaNotFound, used_formula.span.clone()))?;
+		if !bindings.inner.is_empty() {
+			return Err(Spanned::new(
+				ProofError::ExtraBindingsProvided {
+					extra: bindings.inner.iter().map(|b| b.pattern_name.clone()).collect(),
+					functions: Vec::new(),
+				},
+				bindings.span.clone(),
+			));
+		}
+		if !function_bindings.inner.is_empty() {
+			return Err(Spanned::new(
+				ProofError::WrongFunctionBindings {
+					missing: Vec::new(),
+					extra: function_bindings
+						.inner
+						.iter()
+						.map(|binding| AnyFunctionNames {
+							name: binding.inner.0.clone(),
+							arguments_count: binding.inner.1.variables.len(),
+						})
+						.collect(),
+				},
+				function_bindings.span.clone(),
+			));
+		}
+
+		let (from, to) = if used_formula.inner.left_to_right {
+			(&hypothesis.left, &hypothesis.right)
+		} else {
+			(&hypothesis.right, &hypothesis.left)
 		};
+		if current_expr_part != *from {
+			return Err(match_error(MatchError::new(position, MatchErrorKind::Mismatch {
+				expected: from.clone(),
+				actual: current_expr_part,
+			})));
+		}
+		*expr.get_mut(position.borrow()).unwrap() = to.clone();
+		return Ok(expr);
+	}
 
-		let mut any_function_bindings = {
-			let mut binding_map = BTreeMap::new();
-			for binding in &function_bindings.inner {
-				binding_map.insert(binding.0.clone(), binding.1.clone());
+	let formula = get_step_formula(step, context)?;
+
+	let mut bindings = {
+		let mut result = BindingStorage::default();
+		for binding in &bindings.inner {
+			result.insert(binding.pattern_name.clone(), binding.to_value.clone());
+		}
+
+		// Паттерны, которые не были заданы, становятся метапеременными.
+		for binding in &bindings.inner {
+			taken_names.extend(binding.to_value.get_pattern_names());
+		}
+		for name in &formula.left.unknown_patterns_names {
+			if result.get(name).is_none() {
+				let metavariable = fresh_metavariable(name, &taken_names);
+				taken_names.insert(metavariable.clone());
+				context.metavariables.borrow_mut().insert(metavariable.clone());
+				result.insert(name.clone(), ExpressionMeta::Pattern { name: metavariable }.apply(Expression));
 			}
+		}
+		result
+	};
 
-			ManualAnyFunctionBinding::new(binding_map)
+	let mut any_function_bindings = {
+		let mut binding_map = BTreeMap::new();
+		for binding in &function_bindings.inner {
+			binding_map.insert(binding.inner.0.clone(), binding.inner.1.clone());
+		}
+
+		ManualAnyFunctionBinding::new(binding_map)
+	};
+
+	find_bindings_at(
+		current_expr_part,
+		&formula.left.pattern,
+		&mut position.clone(),
+		&mut bindings,
+		&mut any_function_bindings,
+	)
+	.map_err(match_error)?;
+	let apply_error = |error: ApplyError| {
+		let span = function_bindings
+			.inner
+			.iter()
+			.find(|binding| binding.inner.0 == error.function_name())
+			.map(|binding| binding.span.clone())
+			.unwrap_or_else(|| function_bindings.span.clone());
+		Spanned::new(ProofError::CannotApplyBindings(error), span)
+	};
+	let mut current_expr_part =
+		apply_bindings(formula.right.pattern.clone(), &bindings, &any_function_bindings).map_err(apply_error)?;
+
+	// Условия используемой теоремы становятся обязательствами.
+	for hypothesis in &formula.hypotheses {
+		let obligation = Hypothesis {
+			left: apply_bindings(hypothesis.left.clone(), &bindings, &any_function_bindings).map_err(apply_error)?,
+			right: apply_bindings(hypothesis.right.clone(), &bindings, &any_function_bindings).map_err(apply_error)?,
 		};
+		if !is_obligation_discharged(&obligation, &step.discharged_by.inner, context)? {
+			return Err(Spanned::new(ProofError::ObligationNotDischarged { obligation }, used_formula.span.clone()));
+		}
+	}
 
-		find_bindings(current_expr_part, &formula.left.pattern, &mut bindings, &mut any_function_bindings)
-			.ok_or(Spanned::new(ProofError::CannotFindBindings, expr_span.clone()))?;
-		let mut current_expr_part = apply_bindings(formula.right.pattern.clone(), &bindings, &any_function_bindings);
+	std::mem::swap(&mut current_expr_part, expr.get_mut(position.borrow()).unwrap());
 
-		std::mem::swap(&mut current_expr_part, current_expr);
+	Ok(expr)
+}
+
+/// Применяет формулу `rule` к выражению `expr` в позиции `position` так же, как шаг без биндингов. Через это проходят шаги, в которые развернулась тактика, поэтому проверка у них та же самая, что и у записанных шагов. Все спаны ошибок указывают на `rule`.
+pub(crate) fn apply_rule(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	rule: &Spanned<crate::parsing::FormulaPosition>,
+	context: &ProofContext,
+) -> Result<Expression, Spanned<ProofError>> {
+	apply_proof_step(expr.clone(), position, &rule_step(position, rule), context)
+}
 
-		current = expr;
+/// Шаг без биндингов, который применяет `rule` в позиции `position`.
+fn rule_step(position: &ExprPositionOwned, rule: &Spanned<crate::parsing::FormulaPosition>) -> ProofStep {
+	ProofStep {
+		string: "_".to_string(),
+		expr: Spanned::new(None, rule.span.clone()),
+		position: Some(Spanned::new(StepPosition::Path(position.clone()), rule.span.clone())),
+		used_formula: Some(rule.clone()),
+		annotation: rule.span.clone(),
+		bindings: Spanned::new(Vec::new(), rule.span.clone()),
+		function_bindings: Spanned::new(Vec::new(), rule.span.clone()),
+		discharged_by: Spanned::new(Vec::new(), rule.span.clone()),
 	}
+}
 
-	if clear_parsing_info(formula.right.clone()) != current {
-		return Err(Spanned::new(ProofError::LatestStepWrong { actual: current }, proof.span.clone()));
+/// Применяет формулу с функцией `$f` одного аргумента так же, как `apply_rule`, перебирая биндинги функции из `function_binding_candidates`. Возвращает все успешные применения вместе с биндингом.
+pub(crate) fn apply_rule_with_function(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	rule: &Spanned<crate::parsing::FormulaPosition>,
+	context: &ProofContext,
+) -> Vec<((String, AnyFunctionPattern), Expression)> {
+	let step = rule_step(position, rule);
+	function_binding_candidates(expr, position, &step, context)
+		.into_iter()
+		.filter_map(|binding| {
+			let step = with_function_binding(&step, binding.clone());
+			Some((binding, apply_proof_step(expr.clone(), position, &step, context).ok()?))
+		})
+		.collect()
+}
+
+/// Разворачивает тактику и проверяет все получившиеся шаги.
+fn apply_tactic(
+	tactic: &Spanned<Tactic>,
+	expr: Expression,
+	context: &mut ProofContext,
+) -> Result<Expression, Spanned<ProofError>> {
+	let steps = match tactic::expand(tactic, &expr, context) {
+		Ok(steps) => steps,
+		Err(error) => {
+			context.tactic_rules.extend(tactic.inner.used_formulas().map(|rule| rule.inner.clone()));
+			return Err(error);
+		},
+	};
+	context.tactic_rules.extend(steps.iter().map(|step| step.rule.inner.clone()));
+	steps.iter().try_fold(expr, |expr, step| apply_rule(&expr, &step.position, &step.rule, context))
+}
+
+pub(crate) fn lemma_formula(lemma: &Lemma) -> Result<Formula, FormulaError> {
+	Formula::new(
+		clear_parsing_info(lemma.formula.inner.left.clone()),
+		clear_parsing_info(lemma.formula.inner.right.clone()),
+	)
+	.and_then(|x| x.with_hypotheses(hypotheses(&lemma.formula.inner)))
+	.and_then(Formula::reject_metavariables)
+}
+
+/// Проверяет доказательство леммы и добавляет её в контекст. Внутри доказательства леммы видны только её собственные условия и леммы, записанные выше.
+fn check_lemma(lemma: &Lemma, context: &mut ProofContext, errors: &mut Vec<ProofStepError>) {
+	let formula = match lemma_formula(lemma) {
+		Ok(x) => x,
+		Err(error) => {
+			errors.push(ProofStepError::new(Spanned::new(ProofError::FormulaError(error), lemma.formula.span.clone())));
+			return;
+		},
+	};
+	if context.lemmas.contains_key(&lemma.name.inner) {
+		errors.push(ProofStepError::new(Spanned::new(ProofError::LemmaRedefined, lemma.name.span.clone())));
+		return;
 	}
 
+	let mut lemma_context = ProofContext {
+		global_formulas: context.global_formulas,
+		current: context.current.clone(),
+		hypotheses: formula.hypotheses.clone(),
+		lemmas: context.lemmas.clone(),
+		computed: Vec::new(),
+		tactic_rules: Vec::new(),
+		metavariables: RefCell::default(),
+	};
+	check_proof(&lemma.formula, &lemma.proof, &mut lemma_context, errors);
+	context.computed.append(&mut lemma_context.computed);
+	context.tactic_rules.append(&mut lemma_context.tactic_rules);
 
-	Ok(())
+	// Лемма добавляется даже если её доказательство неверно, чтобы не сообщать об ошибках в каждом её использовании.
+	context.lemmas.insert(lemma.name.inner.clone(), formula);
 }
 
-pub fn is_proofs_correct(
-	math: &Math,
-	global_formulas: &BTreeMap<FormulaPosition, Formula>,
-) -> Result<(), Vec<Spanned<ProofError>>> {
-	let mut result = Vec::new();
-	for NamedFormulas { name: _, formulas } in &math.0 {
-		for formula in formulas {
-			if let Some(proof) = &formula.proof {
-				if let Err(error) = is_proof_correct(&formula.formula.inner, proof, global_formulas) {
-					result.push(error);
+/// Проверяет цепочку шагов, которая начинается с выражения `start`. `target` это выражение, к которому цепочка должна прийти, если оно известно заранее, нужно для подсказок. Леммы цепочки добавляются в контекст по мере того, как они встречаются.
+///
+/// Возвращает результат последнего шага и пояснение, откуда он взялся, или `None`, если последний шаг сломался.
+fn check_chain(
+	start: Expression,
+	start_note: Spanned<String>,
+	chain: &ProofChain,
+	target: Option<&Expression>,
+	context: &mut ProofContext,
+	errors: &mut Vec<ProofStepError>,
+) -> Option<(Expression, Spanned<String>)> {
+	let steps = &chain.steps;
+	let tactics = &chain.tactics;
+	let mut lemmas = chain.lemmas.iter().peekable();
+	/// Откуда взялось выражение, с которым сравнивается очередной шаг.
+	enum Previous {
+		/// Начало цепочки.
+		Start(Expression),
+		/// Результат успешного шага с таким индексом.
+		Step(usize, Expression),
+		/// Результат тактики с таким индексом.
+		Tactic(usize, Expression),
+		/// Шаг с таким индексом сломался, сравнивать не с чем.
+		Failed(usize),
+		/// Тактика с таким индексом сломалась.
+		TacticFailed(usize),
+	}
+
+	let note = |previous: &Previous| match previous {
+		Previous::Start(_) => start_note.clone(),
+		Previous::Step(index, _) => Spanned::new(
+			format!("this step continues from the result of step {}", index + 1),
+			step_rule(&steps[*index]).map_or_else(|error| error.span, |rule| rule.span.clone()),
+		),
+		Previous::Tactic(index, _) => Spanned::new(
+			format!("this step continues from the result of `{}` tactic", tactics[*index].1.tactic.inner.name()),
+			tactics[*index].1.tactic.span.clone(),
+		),
+		Previous::Failed(index) => Spanned::new(
+			format!("step {} is broken, so this step is checked from its written expression", index + 1),
+			steps[*index].expr.span.clone(),
+		),
+		Previous::TacticFailed(index) => Spanned::new(
+			format!(
+				"`{}` tactic is broken, so this step is checked from its written expression",
+				tactics[*index].1.tactic.inner.name()
+			),
+			tactics[*index].1.expr.span.clone(),
+		),
+	};
+
+	// Записанное выражение может уточнить метапеременные, оставшиеся с прошлых шагов.
+	let continue_from =
+		|mut expr: Expression, span: &GlobalSpan, previous: &Previous, context: &ProofContext, errors: &mut Vec<_>| {
+			if let Previous::Start(current) | Previous::Step(_, current) | Previous::Tactic(_, current) = previous {
+				if expr != *current {
+					match unify_metavariables(&expr, current, &context.metavariables.borrow()) {
+						Ok(unifier) => expr = unifier.substitute(&expr),
+						Err(_) => errors.push(ProofStepError {
+							error: Spanned::new(ProofError::StepWrong { actual: current.clone() }, span.clone()),
+							notes: vec![note(previous)],
+						}),
+					}
 				}
 			}
+			expr
+		};
+
+	let mut previous = Previous::Start(start);
+
+	// Шаги и тактики идут вперемешку, у тактики записано, сколько шагов перед ней.
+	let mut tactics_iter = tactics.iter().enumerate().peekable();
+	let mut next_step = 0;
+	for item_index in 0.. {
+		while let Some((_, lemma)) = lemmas.next_if(|(before, _)| *before <= item_index) {
+			check_lemma(lemma, context, errors);
 		}
+
+		if let Some((tactic_index, (_, tactic))) = tactics_iter.next_if(|(_, (before, _))| *before <= next_step) {
+			let expr = clear_parsing_info(tactic.expr.inner.clone());
+			let expr = continue_from(expr, &tactic.expr.span, &previous, context, errors);
+			previous = match apply_tactic(&tactic.tactic, expr, context) {
+				Ok(expr) => Previous::Tactic(tactic_index, expr),
+				Err(error) => {
+					errors.push(ProofStepError { error, notes: vec![note(&previous)] });
+					Previous::TacticFailed(tactic_index)
+				},
+			};
+			continue;
+		}
+
+		let index = next_step;
+		let step = match steps.get(index) {
+			Some(step) => step,
+			None => break,
+		};
+		next_step += 1;
+
+		let current = match &previous {
+			Previous::Start(current) | Previous::Step(_, current) | Previous::Tactic(_, current) => Some(current),
+			Previous::Failed(_) | Previous::TacticFailed(_) => None,
+		};
+		let (expr, position) = match parse_proof_step(step, current) {
+			Ok(x) => x,
+			Err(error) => {
+				errors.push(ProofStepError { error, notes: vec![note(&previous)] });
+				previous = Previous::Failed(index);
+				continue;
+			},
+		};
+		if step.expr.inner.is_none() {
+			context.computed.push((step.expr.span.clone(), expr.clone()));
+		}
+		let expr = continue_from(expr, &step.expr.span, &previous, context, errors);
+
+		previous = match apply_proof_step(expr.clone(), &position, step, context) {
+			Ok(expr) => Previous::Step(index, expr),
+			Err(error) => {
+				let mut notes = vec![note(&previous)];
+				if let ProofError::FunctionBindingRedefined { first, .. } = &error.inner {
+					notes.push(Spanned::new("first binding is here".to_string(), first.clone()));
+				}
+				if let ProofError::WrongFunctionBindings { missing, .. } = &error.inner {
+					let rule_span = step_rule(step).map_or_else(|error| error.span, |rule| rule.span.clone());
+					let next = match (tactics_iter.peek(), steps.get(index + 1)) {
+						(Some((_, (before, tactic))), _) if *before <= index + 1 => {
+							Some(clear_parsing_info(tactic.expr.inner.clone()))
+						},
+						(_, Some(next)) => next.expr.inner.clone().map(clear_parsing_info),
+						_ => target.cloned(),
+					};
+					match next.and_then(|next| infer_function_binding(&expr, &position, step, &next, context)) {
+						Some((name, AnyFunctionPattern { pattern, variables })) => notes.push(Spanned::new(
+							format!("try `${}({}) := {}`", name, variables.iter().joined_by(", "), pattern),
+							rule_span,
+						)),
+						None if !missing.is_empty()
+							&& !matches!(&missing[..], [AnyFunctionNames { arguments_count: 1, .. }]) =>
+						{
+							notes.push(Spanned::new(
+								"binding is suggested only when one function of one argument is missing".to_string(),
+								rule_span,
+							))
+						},
+						None => {},
+					}
+				}
+				errors.push(ProofStepError { error, notes });
+				Previous::Failed(index)
+			},
+		};
+	}
+
+	// Леммы после последнего шага ещё могут понадобиться следующей цепочке.
+	for (_, lemma) in lemmas {
+		check_lemma(lemma, context, errors);
+	}
+
+	match previous {
+		Previous::Step(_, ref current) | Previous::Tactic(_, ref current) => Some((current.clone(), note(&previous))),
+		Previous::Start(_) | Previous::Failed(_) | Previous::TacticFailed(_) => None,
 	}
+}
+
+/// Проверяет доказательство. Цепочка шагов может начинаться с любой части формулы и должна прийти к другой части. Если цепочек две, то первая начинается с левой части, вторая с правой, и они должны прийти к одному выражению.
+///
+/// `position` это место доказываемой формулы, её нельзя использовать в тактиках с модулями.
+pub fn is_proof_correct(
+	position: &FormulaPosition,
+	formula: &Spanned<crate::parsing::Formula>,
+	proof: &Spanned<Proof>,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Result<(), Vec<ProofStepError>> {
+	computed_expressions(position, formula, proof, global_formulas).map(|_| ())
+}
+
+/// Проверяет доказательство так же, как `is_proof_correct`, и возвращает выражения, которые посчитаны вместо `_`, вместе с местами `_` в тексте.
+pub fn computed_expressions(
+	position: &FormulaPosition,
+	formula: &Spanned<crate::parsing::Formula>,
+	proof: &Spanned<Proof>,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Result<Vec<(GlobalSpan, Expression)>, Vec<ProofStepError>> {
+	let (context, errors) = checked_context(position, formula, proof, global_formulas);
+	if errors.is_empty() { Ok(context.computed) } else { Err(errors) }
+}
+
+/// Глобальные формулы, которые используют шаги, в которые развернулись тактики доказательства. Формула входит столько раз, сколько шагов её применяют. Доказательство может быть неверным, тогда у сломанных тактик берутся формулы, явно указанные в них.
+pub fn tactic_rules(
+	position: &FormulaPosition,
+	formula: &Spanned<crate::parsing::Formula>,
+	proof: &Spanned<Proof>,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+) -> Vec<FormulaPosition> {
+	let (context, _) = checked_context(position, formula, proof, global_formulas);
+	context
+		.tactic_rules
+		.into_iter()
+		.filter(|rule| rule.module_name != HYPOTHESIS_MODULE && !rule.is_lemma())
+		.map(|rule| FormulaPosition { module_name: rule.module_name, position: rule.position })
+		.collect()
+}
+
+fn checked_context<'a>(
+	position: &FormulaPosition,
+	formula: &Spanned<crate::parsing::Formula>,
+	proof: &Spanned<Proof>,
+	global_formulas: &'a BTreeMap<FormulaPosition, Formula>,
+) -> (ProofContext<'a>, Vec<ProofStepError>) {
+	let mut context = ProofContext {
+		global_formulas,
+		current: position.clone(),
+		hypotheses: hypotheses(&formula.inner),
+		lemmas: BTreeMap::new(),
+		computed: Vec::new(),
+		tactic_rules: Vec::new(),
+		metavariables: RefCell::default(),
+	};
+	let mut errors = Vec::new();
+	check_proof(formula, proof, &mut context, &mut errors);
+	(context, errors)
+}
+
+/// Проверяет доказательство целиком. После сломанного шага проверка не останавливается: следующий шаг проверяется от своего записанного выражения, поэтому возвращаются ошибки всех сломанных шагов.
+fn check_proof(
+	formula: &Spanned<crate::parsing::Formula>,
+	proof: &Spanned<Proof>,
+	context: &mut ProofContext,
+	errors: &mut Vec<ProofStepError>,
+) {
+	let side_expression = |side: Side| match side {
+		Side::Left => (&formula.inner.left, &formula.inner.left_span),
+		Side::Right => (&formula.inner.right, &formula.inner.right_span),
+	};
+
+	let mut ends = Vec::new();
+	for chain in &proof.inner.chains {
+		let side = chain.inner.side;
+		let start_note = Spanned::new(
+			format!("this step starts from the {} side of the formula", side),
+			side_expression(side).1.clone(),
+		);
+		let target = match &proof.inner.chains[..] {
+			[_] => Some(clear_parsing_info(side_expression(side.opposite()).0.clone())),
+			_ => None,
+		};
+		let start = clear_parsing_info(side_expression(side).0.clone());
+		ends.push(check_chain(start, start_note, &chain.inner, target.as_ref(), context, errors));
+	}
+
+	let metavariables = context.metavariables.borrow();
+	match (&proof.inner.chains[..], &ends[..]) {
+		([chain], [Some((current, note))])
+			if unify_metavariables(
+				&clear_parsing_info(side_expression(chain.inner.side.opposite()).0.clone()),
+				current,
+				&metavariables,
+			)
+			.is_err() =>
+		{
+			errors.push(ProofStepError {
+				error: Spanned::new(
+					ProofError::LatestStepWrong { side: chain.inner.side.opposite(), actual: current.clone() },
+					proof.span.clone(),
+				),
+				notes: vec![note.clone()],
+			});
+		},
+		([_, right_chain], [Some((left, left_note)), Some((right, right_note))])
+			if unify_metavariables(left, right, &metavariables).is_err() =>
+		{
+			errors.push(ProofStepError {
+				error: Spanned::new(
+					ProofError::ChainsDontMeet { left: left.clone(), right: right.clone() },
+					right_chain.span.clone(),
+				),
+				notes: vec![left_note.clone(), right_note.clone()],
+			});
+		},
+		_ => {},
+	}
+}
+
+/// Проверяет доказательства в `threads` потоках, `0` означает по потоку на каждое ядро. Результаты возвращаются в том же порядке, что и доказательства, независимо от количества потоков.
+pub fn check_proofs_parallel(
+	proofs: &[(FormulaPosition, &Spanned<crate::parsing::Formula>, &Spanned<Proof>)],
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	threads: usize,
+) -> Vec<Result<(), Vec<ProofStepError>>> {
+	let threads = if threads == 0 { std::thread::available_parallelism().map_or(1, |x| x.get()) } else { threads };
+	let threads = threads.min(proofs.len());
+	if threads <= 1 {
+		return proofs
+			.iter()
+			.map(|(position, formula, proof)| is_proof_correct(position, formula, proof, global_formulas))
+			.collect();
+	}
+
+	// Доказательства сильно отличаются по длине, поэтому потоки берут их по одному, а не делят поровну заранее.
+	let next = AtomicUsize::new(0);
+	let mut results: Vec<(usize, Result<(), Vec<ProofStepError>>)> = std::thread::scope(|scope| {
+		let workers: Vec<_> = (0..threads)
+			.map(|_| {
+				scope.spawn(|| {
+					let mut results = Vec::new();
+					loop {
+						let index = next.fetch_add(1, Ordering::Relaxed);
+						let (position, formula, proof) = match proofs.get(index) {
+							Some(x) => x,
+							None => break results,
+						};
+						results.push((index, is_proof_correct(position, formula, proof, global_formulas)));
+					}
+				})
+			})
+			.collect();
+		workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+	});
+	results.sort_by_key(|(index, _)| *index);
+	results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Проверяет все доказательства в `threads` потоках, см. `check_proofs_parallel`.
+pub fn is_proofs_correct(
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	threads: usize,
+) -> Result<(), Vec<ProofStepError>> {
+	let proofs: Vec<_> = math
+		.0
+		.iter()
+		.flat_map(|NamedFormulas { name, formulas }| {
+			formulas.iter().enumerate().filter_map(move |(index, formula)| {
+				let position = FormulaPosition { module_name: name.clone(), position: index + 1 };
+				Some((position, &formula.formula, formula.proof.as_ref()?))
+			})
+		})
+		.collect();
+
+	let result: Vec<_> = check_proofs_parallel(&proofs, global_formulas, threads)
+		.into_iter()
+		.filter_map(Result::err)
+		.flatten()
+		.collect();
 
 	if result.is_empty() { Ok(()) } else { Err(result) }
 }
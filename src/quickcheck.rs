@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+	binding::Formula,
+	eval::{eval, EvalError, Interpretations, Rational, Value},
+	expr::*,
+	proof::FormulaPosition,
+	utils::joined_by::*,
+};
+
+/// Какие значения подставлять вместо паттерна.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Kind {
+	Number,
+	Bool,
+}
+
+/// Собирает, где стоят паттерны: в аргументах арифметики и сравнений `<`, `>` нужны числа, в логике и условиях `part` нужны `$true` и `$false`. `expected` это то, что нужно на месте самого `expr`.
+fn pattern_kinds(expr: &Expression, expected: Option<Kind>, result: &mut BTreeMap<String, BTreeSet<Kind>>) {
+	use ExpressionMeta::*;
+
+	match &expr.0 {
+		Pattern { name } => {
+			let kinds = result.entry(name.clone()).or_default();
+			kinds.extend(expected);
+		},
+		NamedFunction { name, args } => {
+			let expected_args: Vec<Option<Kind>> = match name.as_str() {
+				"+" | "-" | "*" | "/" | "negative" | "abs" | "sign" | "<" | ">" | "<=" | ">=" => {
+					vec![Some(Kind::Number); args.len()]
+				},
+				"not" | "&" | "|" => vec![Some(Kind::Bool); args.len()],
+				"part" => vec![Some(Kind::Bool), expected, expected],
+				_ => vec![None; args.len()],
+			};
+			for (arg, expected) in args.iter().zip(expected_args) {
+				pattern_kinds(arg, expected, result);
+			}
+		},
+		AnyFunction { args, .. } => args.iter().for_each(|arg| pattern_kinds(arg, None, result)),
+		NamedValue { .. } | IntegerValue { .. } => {},
+	}
+}
+
+/// Генератор псевдослучайных чисел xorshift, его достаточно, чтобы перебирать значения паттернов.
+pub struct Random(u64);
+
+impl Random {
+	pub fn new(seed: u64) -> Random { Random(seed.max(1)) }
+
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	fn below(&mut self, n: u64) -> i128 { (self.next() % n) as i128 }
+
+	/// Небольшая дробь. Ноль и единицы выпадают чаще, потому что на них ломаются формулы с делением и `part`.
+	fn number(&mut self) -> Rational {
+		match self.below(4) {
+			0 => Rational::integer(self.below(3) - 1),
+			1 => Rational::new(self.below(21) - 10, self.below(4) + 1).unwrap(),
+			_ => Rational::integer(self.below(21) - 10),
+		}
+	}
+
+	fn value(&mut self, kinds: &BTreeSet<Kind>) -> Value {
+		let kind = match kinds.iter().collect::<Vec<_>>()[..] {
+			[kind] => *kind,
+			_ if self.below(2) == 0 => Kind::Bool,
+			_ => Kind::Number,
+		};
+		match kind {
+			Kind::Number => Value::Number(self.number()),
+			Kind::Bool => Value::Bool(self.below(2) == 0),
+		}
+	}
+}
+
+/// Значения паттернов, при которых стороны формулы различаются.
+#[derive(Debug)]
+pub struct Counterexample {
+	pub position: FormulaPosition,
+	pub values: BTreeMap<String, Value>,
+	pub left: Value,
+	pub right: Value,
+}
+
+impl std::fmt::Display for Counterexample {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let values = self.values.iter().map(|(name, value)| format!("{} := {}", name, value)).joined_by(", ");
+		let Counterexample { position, left, right, .. } = self;
+		write!(f, "`{}` is false for {}: left side is `{}`, right side is `{}`", position, values, left, right)
+	}
+}
+
+/// Результат проверки одной формулы.
+#[derive(Debug)]
+pub enum Outcome {
+	/// Стороны совпали на всех `tests` значениях.
+	Passed,
+	Failed(Counterexample),
+	/// Формулу нельзя вычислить, например в ней есть `$f` или функция без встроенного смысла.
+	Skipped(EvalError),
+	/// Условия формулы не выполнились ни на одном наборе значений.
+	Vacuous,
+}
+
+/// Значения сторон формулы, или `None`, если не выполняется какое-то её условие.
+fn sides(
+	formula: &Formula,
+	values: &BTreeMap<String, Value>,
+	interpretations: &Interpretations,
+) -> Result<Option<(Value, Value)>, EvalError> {
+	let eval = |expr| eval(expr, values, interpretations);
+	for hypothesis in &formula.hypotheses {
+		if eval(&hypothesis.left)? != eval(&hypothesis.right)? {
+			return Ok(None);
+		}
+	}
+	Ok(Some((eval(&formula.left.pattern)?, eval(&formula.right.pattern)?)))
+}
+
+/// Проверяет формулу на `tests` случайных наборах значений паттернов. Наборы, на которых не выполняются условия формулы или числа слишком растут, пропускаются.
+pub fn check_formula(
+	position: &FormulaPosition,
+	formula: &Formula,
+	interpretations: &Interpretations,
+	tests: usize,
+	random: &mut Random,
+) -> Outcome {
+	let mut kinds = BTreeMap::new();
+	for hypothesis in &formula.hypotheses {
+		pattern_kinds(&hypothesis.left, None, &mut kinds);
+		pattern_kinds(&hypothesis.right, None, &mut kinds);
+	}
+	pattern_kinds(&formula.left.pattern, None, &mut kinds);
+	pattern_kinds(&formula.right.pattern, None, &mut kinds);
+
+	let mut passed = 0;
+	for _ in 0..tests * 10 {
+		if passed == tests {
+			break;
+		}
+		let values: BTreeMap<String, Value> =
+			kinds.iter().map(|(name, kinds)| (name.clone(), random.value(kinds))).collect();
+		match sides(formula, &values, interpretations) {
+			Ok(None) | Err(EvalError::Overflow) => {},
+			Ok(Some((left, right))) if left == right => passed += 1,
+			Ok(Some((left, right))) => {
+				return Outcome::Failed(Counterexample { position: position.clone(), values, left, right });
+			},
+			Err(error) => return Outcome::Skipped(error),
+		}
+	}
+	if passed == 0 { Outcome::Vacuous } else { Outcome::Passed }
+}
+
+/// Проверяет все формулы случайными значениями, функции берутся из `interpretations`. Одно и то же `seed` даёт одни и те же значения.
+pub fn quickcheck(
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	interpretations: &Interpretations,
+	tests: usize,
+	seed: u64,
+) -> Vec<(FormulaPosition, Outcome)> {
+	let mut random = Random::new(seed);
+	global_formulas
+		.iter()
+		.map(|(position, formula)| {
+			(position.clone(), check_formula(position, formula, interpretations, tests, &mut random))
+		})
+		.collect()
+}
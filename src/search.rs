@@ -0,0 +1,278 @@
+use std::{
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+	time::{Duration, Instant},
+};
+
+use petgraph::{
+	visit::{Bfs, Reversed},
+	Graph,
+};
+use thiserror::Error;
+
+use crate::{
+	binding::{find_bindings, AnyFunctionPattern, Binding, BindingStorage, Formula, ManualAnyFunctionBinding},
+	expr::*,
+	index::{FormulaIndex, IndexedRule},
+	parsing::{self, Math, HYPOTHESIS_MODULE},
+	print::PlainStep,
+	proof::{apply_rule, apply_rule_with_function, dependency_graph, Dependency, FormulaPosition, ProofContext},
+	utils::span::*,
+};
+
+/// Когда прекращать поиск.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+	/// Сколько разных выражений можно посетить с обеих сторон вместе.
+	pub max_nodes: usize,
+	pub timeout: Option<Duration>,
+}
+
+impl Default for SearchLimits {
+	fn default() -> Self { SearchLimits { max_nodes: 20_000, timeout: None } }
+}
+
+#[derive(Debug, Error)]
+pub enum SearchError {
+	#[error("formula is not found")]
+	FormulaNotFound,
+	#[error("both sides of formula are the same, there is nothing to prove")]
+	AlreadyEqual,
+	#[error("proof is not found, limit of {nodes} expressions is reached")]
+	NodeLimit { nodes: usize },
+	#[error("proof is not found in {seconds} seconds, {nodes} expressions visited", seconds = timeout.as_secs_f64())]
+	Timeout { timeout: Duration, nodes: usize },
+	#[error("proof is not found, all {nodes} reachable expressions are visited")]
+	Exhausted { nodes: usize },
+}
+
+/// Биндинг функции `$f`, который поиск подобрал для формулы.
+type FunctionBinding = Option<(String, AnyFunctionPattern)>;
+
+/// Как было получено выражение: из какого выражения, в какой позиции, какой формулой и с каким биндингом функции.
+type Parent = Option<(Expression, ExprPositionOwned, IndexedRule, FunctionBinding)>;
+
+/// Одна сторона двунаправленного поиска в ширину.
+#[derive(Default)]
+struct Frontier {
+	parents: HashMap<Expression, Parent>,
+	queue: VecDeque<Expression>,
+}
+
+impl Frontier {
+	fn new(start: Expression) -> Self {
+		let mut result = Frontier::default();
+		result.parents.insert(start.clone(), None);
+		result.queue.push_back(start);
+		result
+	}
+
+	/// Путь от начала стороны до `expr`, в порядке от начала.
+	fn path(&self, expr: &Expression) -> Vec<(Expression, ExprPositionOwned, IndexedRule, FunctionBinding, Expression)> {
+		let mut result = Vec::new();
+		let mut current = expr.clone();
+		while let Some(Some((parent, position, rule, function))) = self.parents.get(&current) {
+			result.push((parent.clone(), position.clone(), rule.clone(), function.clone(), current));
+			current = parent.clone();
+		}
+		result.reverse();
+		result
+	}
+}
+
+pub(crate) fn to_parsing(rule: &IndexedRule) -> parsing::FormulaPosition {
+	parsing::FormulaPosition {
+		module_name: rule.formula.module_name.clone(),
+		position: rule.formula.position,
+		left_to_right: rule.left_to_right,
+		lemma: false,
+	}
+}
+
+/// Формулы, которые можно использовать в доказательстве `position`: все, кроме неё самой и тех, что доказываются через неё.
+pub(crate) fn allowed_formulas<'a>(
+	math: &Math,
+	global_formulas: &'a BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+) -> BTreeMap<&'a FormulaPosition, &'a Formula> {
+	allowed_formulas_in(&dependency_graph(math), global_formulas, position)
+}
+
+/// То же самое, что и `allowed_formulas`, по уже построенному графу зависимостей, чтобы не строить его для каждой формулы.
+pub(crate) fn allowed_formulas_in<'a>(
+	graph: &Graph<FormulaPosition, Dependency>,
+	global_formulas: &'a BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+) -> BTreeMap<&'a FormulaPosition, &'a Formula> {
+	let mut forbidden = BTreeSet::new();
+	forbidden.insert(position.clone());
+	if let Some(start) = graph.node_indices().find(|node| graph[*node] == *position) {
+		let reversed = Reversed(graph);
+		let mut bfs = Bfs::new(reversed, start);
+		while let Some(node) = bfs.next(reversed) {
+			forbidden.insert(graph[node].clone());
+		}
+	}
+	global_formulas.iter().filter(|(position, _)| !forbidden.contains(position)).collect()
+}
+
+/// Поиск цепочки шагов между двумя выражениями внутри доказательства формулы: двунаправленный поиск в ширину от одного выражения вперёд и от другого назад, пока стороны не встретятся. Используются условия доказываемой формулы и формулы без функций `$f` или с одной функцией одного аргумента. Биндинг такой функции подбирается по выражению, к которому применяется формула: это подвыражение, в котором аргумент функции заменён на переменную.
+pub struct PathSearch<'a> {
+	index: FormulaIndex,
+	context: ProofContext<'a>,
+}
+
+impl<'a> PathSearch<'a> {
+	/// Поиск для доказательства формулы `position`, он не использует формулы, которые доказываются через неё.
+	pub fn new(
+		math: &Math,
+		global_formulas: &'a BTreeMap<FormulaPosition, Formula>,
+		position: &FormulaPosition,
+	) -> Result<Self, SearchError> {
+		let formula = global_formulas.get(position).ok_or(SearchError::FormulaNotFound)?;
+		let allowed = allowed_formulas(math, global_formulas, position);
+		let mut index = FormulaIndex::default();
+		for (position, formula) in &allowed {
+			if !matches!(formula.left.anyfunction_names[..], [] | [(_, 1)]) {
+				continue;
+			}
+			// Сторона из одного паттерна подходит к любому выражению и ничего не даёт поиску.
+			for (side, left_to_right) in [(&formula.left.pattern, true), (&formula.right.pattern, false)] {
+				if !matches!(side.0, ExpressionMeta::Pattern { .. }) {
+					index.insert(side, IndexedRule { formula: (*position).clone(), left_to_right });
+				}
+			}
+		}
+		for (number, hypothesis) in formula.hypotheses.iter().enumerate() {
+			let position = FormulaPosition { module_name: HYPOTHESIS_MODULE.to_string(), position: number + 1 };
+			index.insert(&hypothesis.left, IndexedRule { formula: position.clone(), left_to_right: true });
+			index.insert(&hypothesis.right, IndexedRule { formula: position, left_to_right: false });
+		}
+
+		let context = ProofContext {
+			global_formulas,
+			current: position.clone(),
+			hypotheses: formula.hypotheses.clone(),
+			lemmas: BTreeMap::new(),
+			computed: Vec::new(),
+			tactic_rules: Vec::new(),
+			metavariables: RefCell::default(),
+		};
+		Ok(PathSearch { index, context })
+	}
+
+	/// Ищет шаги, которые превращают `from` в `to`. Каждый шаг проходит через ту же проверку, что и записанные шаги, поэтому найденные шаги верны, если `global_formulas` получены из `math`.
+	pub fn find(
+		&self,
+		from: &Expression,
+		to: &Expression,
+		limits: &SearchLimits,
+	) -> Result<Vec<PlainStep>, SearchError> {
+		let PathSearch { index, context } = self;
+		let global_formulas = context.global_formulas;
+		let started = Instant::now();
+		let mut sides = [Frontier::new(from.clone()), Frontier::new(to.clone())];
+
+		let meeting = 'search: loop {
+			let nodes = sides[0].parents.len() + sides[1].parents.len();
+			if let Some(timeout) = limits.timeout {
+				if started.elapsed() > timeout {
+					return Err(SearchError::Timeout { timeout, nodes });
+				}
+			}
+
+			// Расширяется сторона с меньшей очередью, так поиск растёт медленнее.
+			let current = match (sides[0].queue.is_empty(), sides[1].queue.is_empty()) {
+				(true, true) => return Err(SearchError::Exhausted { nodes }),
+				(false, true) => 0,
+				(true, false) => 1,
+				(false, false) => usize::from(sides[1].queue.len() < sides[0].queue.len()),
+			};
+			let expr = sides[current].queue.pop_front().unwrap();
+			let names = expr.get_pattern_names();
+
+			for (position, rule) in index.candidates_everywhere(&expr) {
+				let used = Spanned::new(to_parsing(&rule), GlobalSpan(0..0));
+				let has_function =
+					global_formulas.get(&rule.formula).is_some_and(|formula| !formula.left.anyfunction_names.is_empty());
+				let results: Vec<(FunctionBinding, Expression)> = if has_function {
+					let results = apply_rule_with_function(&expr, &position, &used, context);
+					results.into_iter().map(|(binding, result)| (Some(binding), result)).collect()
+				} else {
+					apply_rule(&expr, &position, &used, context).into_iter().map(|result| (None, result)).collect()
+				};
+				for (function, result) in results {
+					// Метапеременные ничем не уточнить, такой шаг бесполезен.
+					if result.get_pattern_names().iter().any(|name| is_metavariable(name) && !names.contains(name)) {
+						continue;
+					}
+					if sides[current].parents.contains_key(&result) {
+						continue;
+					}
+					if sides[0].parents.len() + sides[1].parents.len() >= limits.max_nodes {
+						return Err(SearchError::NodeLimit { nodes: limits.max_nodes });
+					}
+					let parent = Some((expr.clone(), position.clone(), rule.clone(), function));
+					sides[current].parents.insert(result.clone(), parent);
+					if sides[1 - current].parents.contains_key(&result) {
+						break 'search result;
+					}
+					sides[current].queue.push_back(result);
+				}
+			}
+		};
+
+		let mut result: Vec<PlainStep> = sides[0]
+			.path(&meeting)
+			.into_iter()
+			.map(|(expr, position, rule, function, _)| PlainStep {
+				expr,
+				position,
+				rule: to_parsing(&rule),
+				bindings: Vec::new(),
+				function_bindings: function.into_iter().collect(),
+			})
+			.collect();
+
+		// Шаги правой стороны идут в обратную сторону, поэтому формула разворачивается, а паттерны, которые пропадают при обратном применении, задаются явно.
+		for (to, position, rule, function, from) in sides[1].path(&meeting).into_iter().rev() {
+			let mut bindings = Vec::new();
+			if let Some(formula) = global_formulas.get(&rule.formula) {
+				let (left, right) = if rule.left_to_right {
+					(&formula.left, &formula.right)
+				} else {
+					(&formula.right, &formula.left)
+				};
+				let mut storage = BindingStorage::default();
+				let mut any_function_bindings = ManualAnyFunctionBinding::new(function.clone().into_iter().collect());
+				let part = to.get(&position).expect("position is taken from this expression").clone();
+				find_bindings(part, &left.pattern, &mut storage, &mut any_function_bindings)
+					.expect("formula was applied at this position");
+				for name in &right.unknown_patterns_names {
+					if let Some(value) = storage.get(name) {
+						bindings.push(Binding::new(name.clone(), value.clone()));
+					}
+				}
+			}
+			let rule = IndexedRule { left_to_right: !rule.left_to_right, ..rule };
+			let function_bindings = function.into_iter().collect();
+			result.push(PlainStep { expr: from, position, rule: to_parsing(&rule), bindings, function_bindings });
+		}
+
+		Ok(result)
+	}
+}
+
+/// Ищет доказательство формулы `position` через `PathSearch`. Возвращает шаги одной цепочки от левой части.
+pub fn prove(
+	math: &Math,
+	global_formulas: &BTreeMap<FormulaPosition, Formula>,
+	position: &FormulaPosition,
+	limits: &SearchLimits,
+) -> Result<Vec<PlainStep>, SearchError> {
+	let formula = global_formulas.get(position).ok_or(SearchError::FormulaNotFound)?;
+	if formula.left.pattern == formula.right.pattern {
+		return Err(SearchError::AlreadyEqual);
+	}
+	PathSearch::new(math, global_formulas, position)?.find(&formula.left.pattern, &formula.right.pattern, limits)
+}
@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashSet};
+
+use thiserror::Error;
+
+use crate::{
+	expr::{is_metavariable, ExprPositionOwned, Expression, ExpressionExtension},
+	index::FormulaIndex,
+	parsing::{FormulaPosition, Tactic},
+	proof::{apply_rule, ProofContext, ProofError},
+	utils::{joined_by::*, span::*},
+};
+
+/// Сколько шагов может сделать одна тактика. Защищает от формул, которые бесконечно увеличивают выражение.
+pub const MAX_STEPS: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum TacticError {
+	#[error("formula `{rule}` cannot be applied to {expr}")]
+	NotApplicable { rule: FormulaPosition, expr: Expression },
+	#[error("position @{} is not found in {expr}", position.0.iter().joined_by("."))]
+	PositionNotFound { position: ExprPositionOwned, expr: Expression },
+	#[error("tactic came back to {expr} after {steps} steps, it will never stop")]
+	Loop { expr: Expression, steps: usize },
+	#[error("tactic made more than {MAX_STEPS} steps")]
+	TooManySteps,
+	#[error("module has no formulas that can be used here")]
+	ModuleNotFound,
+}
+
+/// Обычный шаг доказательства, в который развернулась тактика: применить `rule` в позиции `position` к результату предыдущего шага.
+#[derive(Clone, Debug)]
+pub struct ExpandedStep {
+	pub position: ExprPositionOwned,
+	pub rule: Spanned<FormulaPosition>,
+	/// Результат шага.
+	pub expr: Expression,
+}
+
+fn size(expr: &Expression) -> usize {
+	let mut result = 0;
+	expr.travel_positions(|_, _| result += 1);
+	result
+}
+
+/// Применяет формулу так же, как обычный шаг без биндингов. Результаты с новыми метапеременными тактикам не нужны: их потом нельзя уточнить.
+fn try_apply(
+	expr: &Expression,
+	position: &ExprPositionOwned,
+	rule: &Spanned<FormulaPosition>,
+	context: &ProofContext,
+) -> Result<Option<Expression>, Spanned<ProofError>> {
+	match apply_rule(expr, position, rule, context) {
+		Ok(result) => {
+			let before = expr.get_pattern_names();
+			let introduced =
+				result.get_pattern_names().into_iter().any(|name| is_metavariable(&name) && !before.contains(&name));
+			Ok(if introduced { None } else { Some(result) })
+		},
+		Err(error) => match error.inner {
+			ProofError::FormulaNotFound => Err(error),
+			_ => Ok(None),
+		},
+	}
+}
+
+/// Применяет формулу в первом подходящем месте при обходе выражения от корня.
+fn apply_anywhere(
+	expr: &Expression,
+	rule: &Spanned<FormulaPosition>,
+	context: &ProofContext,
+) -> Result<Option<ExpandedStep>, Spanned<ProofError>> {
+	let mut positions = Vec::new();
+	expr.travel_positions(|_, position| positions.push(ExprPositionOwned(position.0.to_vec())));
+	for position in positions {
+		if let Some(result) = try_apply(expr, &position, rule, context)? {
+			return Ok(Some(ExpandedStep { position, rule: rule.clone(), expr: result }));
+		}
+	}
+	Ok(None)
+}
+
+/// Разворачивает тактику, применённую к выражению `expr`, в обычные шаги. Шаги отсюда не считаются доказанными: их надо проверить так же, как записанные вручную.
+pub(crate) fn expand(
+	tactic: &Spanned<Tactic>,
+	expr: &Expression,
+	context: &ProofContext,
+) -> Result<Vec<ExpandedStep>, Spanned<ProofError>> {
+	let error = |error: TacticError| Spanned::new(ProofError::TacticFailed(error), tactic.span.clone());
+
+	let mut result: Vec<ExpandedStep> = Vec::new();
+	let current = |result: &[ExpandedStep]| result.last().map_or(expr, |step| &step.expr).clone();
+	match &tactic.inner {
+		Tactic::Repeat { rule, at } => {
+			let mut seen = HashSet::new();
+			seen.insert(expr.clone());
+			loop {
+				let current = current(&result);
+				let step = match at {
+					Some(at) => {
+						if current.get(&at.inner).is_err() {
+							return Err(Spanned::new(
+								ProofError::TacticFailed(TacticError::PositionNotFound {
+									position: at.inner.clone(),
+									expr: current,
+								}),
+								at.span.clone(),
+							));
+						}
+						try_apply(&current, &at.inner, rule, context)?.map(|result| ExpandedStep {
+							position: at.inner.clone(),
+							rule: rule.clone(),
+							expr: result,
+						})
+					},
+					None => apply_anywhere(&current, rule, context)?,
+				};
+				let step = match step {
+					Some(step) => step,
+					None if result.is_empty() => {
+						return Err(error(TacticError::NotApplicable { rule: rule.inner.clone(), expr: current }));
+					},
+					None => break,
+				};
+				if !seen.insert(step.expr.clone()) {
+					return Err(error(TacticError::Loop { expr: step.expr, steps: result.len() + 1 }));
+				}
+				if result.len() == MAX_STEPS {
+					return Err(error(TacticError::TooManySteps));
+				}
+				result.push(step);
+			}
+		},
+		Tactic::Rewrite { rules } => {
+			for rule in rules {
+				let current = current(&result);
+				match apply_anywhere(&current, rule, context)? {
+					Some(step) => result.push(step),
+					None => {
+						return Err(Spanned::new(
+							ProofError::TacticFailed(TacticError::NotApplicable {
+								rule: rule.inner.clone(),
+								expr: current,
+							}),
+							rule.span.clone(),
+						));
+					},
+				}
+			}
+		},
+		Tactic::Simp { modules } => {
+			// Доказываемая формула и формулы ниже неё в том же модуле исключаются, иначе `simp` мог бы доказать её через саму себя.
+			let mut formulas = BTreeMap::new();
+			for module in modules {
+				let before = formulas.len();
+				formulas.extend(
+					context
+						.global_formulas
+						.iter()
+						.filter(|(position, _)| {
+							position.module_name == module.inner && context.current.can_use_from_module(position)
+						})
+						.map(|(position, formula)| (position.clone(), formula.clone())),
+				);
+				if formulas.len() == before {
+					return Err(Spanned::new(ProofError::TacticFailed(TacticError::ModuleNotFound), module.span.clone()));
+				}
+			}
+			let module_span = |name: &str| modules.iter().find(|module| module.inner == name).unwrap().span.clone();
+			let index = FormulaIndex::new(&formulas);
+
+			// Размер выражения строго уменьшается на каждом шаге, поэтому `simp` всегда останавливается.
+			loop {
+				let current = current(&result);
+				let mut next = None;
+				for (position, candidate) in index.candidates_everywhere(&current) {
+					let rule = Spanned::new(
+						FormulaPosition {
+							module_name: candidate.formula.module_name.clone(),
+							position: candidate.formula.position,
+							left_to_right: candidate.left_to_right,
+							lemma: false,
+						},
+						module_span(&candidate.formula.module_name),
+					);
+					if let Some(applied) = try_apply(&current, &position, &rule, context)? {
+						if size(&applied) < size(&current) {
+							next = Some(ExpandedStep { position, rule, expr: applied });
+							break;
+						}
+					}
+				}
+				match next {
+					Some(step) if result.len() < MAX_STEPS => result.push(step),
+					Some(_) => return Err(error(TacticError::TooManySteps)),
+					None => break,
+				}
+			}
+		},
+	}
+	Ok(result)
+}
@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::{binding::BindingStorage, expr::*, utils::apply::*};
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum UnifyError {
+	#[error("cannot unify `{left}` with `{right}`")]
+	Clash { left: Expression, right: Expression },
+	#[error("pattern `{pattern}` occurs in `{inside}`")]
+	Occurs { pattern: String, inside: Expression },
+}
+
+/// Синтаксическая унификация двух выражений, в которых с обоих сторон могут быть паттерны. Возвращает наиболее общий унификатор: после применения его через `BindingStorage::substitute` к обоим выражениям они становятся равны. `AnyFunction` считается обычной функцией со своим именем, то есть `$f(a)` унифицируется только с `$f(...)` того же числа аргументов.
+///
+/// Паттерны обоих выражений находятся в одном пространстве имён, поэтому если выражения взяты из разных формул, то перед унификацией их нужно разделить через `rename_patterns`.
+pub fn unify(left: &Expression, right: &Expression) -> Result<BindingStorage, UnifyError> {
+	unify_all(std::iter::once((left, right)))
+}
+
+/// Унификация, в которой переменными считаются только `metavariables`, метапеременные, созданные проверкой доказательства, а остальные паттерны ведут себя как константы. Так сравниваются выражения внутри доказательства.
+pub fn unify_metavariables(
+	left: &Expression,
+	right: &Expression,
+	metavariables: &BTreeSet<String>,
+) -> Result<BindingStorage, UnifyError> {
+	unify_by(std::iter::once((left, right)), &|name| metavariables.contains(name))
+}
+
+/// Унификация нескольких пар выражений одной общей подстановкой.
+pub fn unify_all<'a, I>(pairs: I) -> Result<BindingStorage, UnifyError>
+where I: IntoIterator<Item = (&'a Expression, &'a Expression)> {
+	unify_by(pairs, &|_| true)
+}
+
+fn unify_by<'a, I>(pairs: I, is_variable: &dyn Fn(&str) -> bool) -> Result<BindingStorage, UnifyError>
+where I: IntoIterator<Item = (&'a Expression, &'a Expression)> {
+	let mut substitution = BTreeMap::new();
+	for (left, right) in pairs {
+		unify_inner(left, right, is_variable, &mut substitution)?;
+	}
+
+	// Подстановка строилась треугольной: значения могут ссылаться на другие связанные паттерны. Раскрываем их, чтобы унификатор был идемпотентным.
+	let mut result = BindingStorage::default();
+	for (name, value) in &substitution {
+		result.insert(name.clone(), resolve(value, &substitution));
+	}
+	Ok(result)
+}
+
+/// Переименовывает все паттерны выражения, добавляя к ним суффикс. Нужно для разделения переменных двух формул перед унификацией.
+pub fn rename_patterns(expr: &Expression, suffix: &str) -> Expression {
+	let mut result = expr.clone();
+	result.travel_mut(&mut |expr| {
+		if let ExpressionMeta::Pattern { name } = &mut expr.0 {
+			name.push_str(suffix);
+		}
+	});
+	result
+}
+
+/// Идёт по цепочке связанных паттернов, пока не найдёт несвязанный паттерн или не-паттерн.
+fn walk<'a>(mut expr: &'a Expression, substitution: &'a BTreeMap<String, Expression>) -> &'a Expression {
+	use ExpressionMeta::*;
+
+	while let Pattern { name } = &expr.0 {
+		match substitution.get(name) {
+			Some(value) => expr = value,
+			None => break,
+		}
+	}
+	expr
+}
+
+/// Полностью применяет треугольную подстановку к выражению.
+fn resolve(expr: &Expression, substitution: &BTreeMap<String, Expression>) -> Expression {
+	use ExpressionMeta::*;
+
+	let expr = walk(expr, substitution);
+	match &expr.0 {
+		AnyFunction { name, args } => AnyFunction {
+			name: name.clone(),
+			args: args.iter().map(|arg| resolve(arg, substitution)).collect(),
+		}
+		.apply(Expression),
+		NamedFunction { name, args } => NamedFunction {
+			name: name.clone(),
+			args: args.iter().map(|arg| resolve(arg, substitution)).collect(),
+		}
+		.apply(Expression),
+		Pattern { .. } | NamedValue { .. } | IntegerValue { .. } => expr.clone(),
+	}
+}
+
+fn occurs(pattern: &str, expr: &Expression, substitution: &BTreeMap<String, Expression>) -> bool {
+	use ExpressionMeta::*;
+
+	let expr = walk(expr, substitution);
+	match &expr.0 {
+		Pattern { name } => name == pattern,
+		AnyFunction { args, .. } | NamedFunction { args, .. } => args.iter().any(|arg| occurs(pattern, arg, substitution)),
+		NamedValue { .. } | IntegerValue { .. } => false,
+	}
+}
+
+fn unify_inner(
+	left: &Expression,
+	right: &Expression,
+	is_variable: &dyn Fn(&str) -> bool,
+	substitution: &mut BTreeMap<String, Expression>,
+) -> Result<(), UnifyError> {
+	use ExpressionMeta::*;
+
+	let mut stack = vec![(left.clone(), right.clone())];
+	while let Some((left, right)) = stack.pop() {
+		let left = walk(&left, substitution).clone();
+		let right = walk(&right, substitution).clone();
+
+		match (&left.0, &right.0) {
+			(Pattern { name: a }, Pattern { name: b }) if a == b => {},
+			(Pattern { name }, _) if is_variable(name) => bind(name, right, substitution)?,
+			(_, Pattern { name }) if is_variable(name) => bind(name, left, substitution)?,
+			(AnyFunction { name: a, args: args_a }, AnyFunction { name: b, args: args_b })
+			| (NamedFunction { name: a, args: args_a }, NamedFunction { name: b, args: args_b })
+				if a == b && args_a.len() == args_b.len() =>
+			{
+				stack.extend(args_a.iter().cloned().zip(args_b.iter().cloned()));
+			}
+			(NamedValue { name: a }, NamedValue { name: b }) if a == b => {},
+			(IntegerValue { value: a }, IntegerValue { value: b }) if a == b => {},
+			_ => {
+				return Err(UnifyError::Clash { left: resolve(&left, substitution), right: resolve(&right, substitution) })
+			},
+		}
+	}
+	Ok(())
+}
+
+fn bind(name: &str, value: Expression, substitution: &mut BTreeMap<String, Expression>) -> Result<(), UnifyError> {
+	if occurs(name, &value, substitution) {
+		return Err(UnifyError::Occurs { pattern: name.to_string(), inside: resolve(&value, substitution) });
+	}
+	substitution.insert(name.to_string(), value);
+	Ok(())
+}
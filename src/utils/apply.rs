@@ -27,7 +27,7 @@ pub trait Apply {
 		Self: Deref,
 		F: FnOnce(&'a Self::Target) -> R,
 	{
-		f(&*self)
+		f(self)
 	}
 
 	fn apply_deref_mut<'a, F, R>(&'a mut self, f: F) -> R
@@ -19,6 +19,21 @@ pub struct Spanned<T> {
 	pub inner: T,
 }
 
+/// Ошибка вместе с пояснениями, которые указывают на другие места в исходнике.
+#[derive(Clone, Debug)]
+pub struct Diagnostic<T> {
+	pub error: Spanned<T>,
+	pub notes: Vec<Spanned<String>>,
+}
+
+impl<T> Diagnostic<T> {
+	pub fn new(error: Spanned<T>) -> Self { Diagnostic { error, notes: Vec::new() } }
+}
+
+impl<T: Display> Diagnostic<T> {
+	pub fn print_error_snippet(&self, string: &str) { self.error.print_error_snippet_with_notes(string, &self.notes) }
+}
+
 impl GlobalSpan {
 	pub fn localize_span(self, start: usize) -> LocalSpan { LocalSpan(self.0.start - start..self.0.end - start) }
 }
@@ -34,10 +49,16 @@ impl<T> Spanned<T> {
 }
 
 impl<T: Display> Spanned<T> {
-	pub fn print_error_snippet<'a>(&self, string: &'a str) {
+	pub fn print_error_snippet(&self, string: &str) { self.print_error_snippet_with_notes(string, &[]) }
+
+	/// Печатает ошибку вместе с пояснениями, которые указывают на другие места в исходнике.
+	pub fn print_error_snippet_with_notes(&self, string: &str, notes: &[Spanned<String>]) {
 		let text = self.inner.to_string();
-		let (line_no, line_range_start) = find_line_number(string, self.span.0.start);
-		let (_, line_range_end) = find_line_number(string, self.span.0.end);
+		let start = notes.iter().map(|note| note.span.0.start).fold(self.span.0.start, usize::min);
+		let end = notes.iter().map(|note| note.span.0.end).fold(self.span.0.end, usize::max);
+		let (line_no, line_range_start) = find_line_number(string, start);
+		let (_, line_range_end) = find_line_number(string, end);
+		let local = |span: &GlobalSpan| (span.0.start - line_range_start.start, span.0.end - line_range_start.start);
 		let snippet = Snippet {
 			title: Some(Annotation { label: Some(&text), id: None, annotation_type: AnnotationType::Error }),
 			footer: vec![],
@@ -46,11 +67,17 @@ impl<T: Display> Spanned<T> {
 				line_start: line_no,
 				origin: None,
 				fold: true,
-				annotations: vec![SourceAnnotation {
+				annotations: std::iter::once(SourceAnnotation {
 					label: "",
 					annotation_type: AnnotationType::Error,
-					range: (self.span.0.start - line_range_start.start, self.span.0.end - line_range_start.start),
-				}],
+					range: local(&self.span),
+				})
+				.chain(notes.iter().map(|note| SourceAnnotation {
+					label: &note.inner,
+					annotation_type: AnnotationType::Note,
+					range: local(&note.span),
+				}))
+				.collect(),
 			}],
 			opt: FormatOptions { color: true, ..Default::default() },
 		};
@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use fopply::{binding::*, parsing::*, read_fpl, utils::char_index::*};
+use fopply::{binding::*, parsing::*, read_fpl, unify::*, utils::char_index::*};
 
 #[test]
 fn test() {
@@ -19,7 +19,7 @@ fn test() {
 	let mut bindings = BindingStorage::default();
 
 	find_bindings(expression, &clear_parsing_info(formula.left), &mut bindings, &mut any_function_bindings).unwrap();
-	let result = apply_bindings(clear_parsing_info(formula.right), &bindings, &any_function_bindings);
+	let result = apply_bindings(clear_parsing_info(formula.right), &bindings, &any_function_bindings).unwrap();
 
 	let should_be = parser::expr_normalized("part(not(b = 0), a*part($true, 1, $undefined), a)").unwrap();
 	let should_be = clear_parsing_info(should_be);
@@ -37,10 +37,10 @@ fn test2() {
 
 	let mut bindings = BindingStorage::default();
 
-	bindings.add(parser::binding("x := b = 0").unwrap());
+	bindings.add(parser::binding("x := b = 0").unwrap()).unwrap();
 
 	find_bindings(expression, &clear_parsing_info(formula.right), &mut bindings, &mut any_function_bindings).unwrap();
-	let result = apply_bindings(clear_parsing_info(formula.left), &bindings, &any_function_bindings);
+	let result = apply_bindings(clear_parsing_info(formula.left), &bindings, &any_function_bindings).unwrap();
 
 	let should_be = parser::expr_normalized("part(b = 0, a, a)").unwrap();
 	let should_be = clear_parsing_info(should_be);
@@ -65,7 +65,7 @@ fn test3() {
 	let mut bindings = BindingStorage::default();
 
 	find_bindings(expression, &clear_parsing_info(formula.left), &mut bindings, &mut any_function_bindings).unwrap();
-	let result = apply_bindings(clear_parsing_info(formula.right), &bindings, &any_function_bindings);
+	let result = apply_bindings(clear_parsing_info(formula.right), &bindings, &any_function_bindings).unwrap();
 
 	let should_be =
 		parser::expr_normalized("part(not(b = 0), a*part($true & not(b = 0), 1, $undefined), a)").unwrap();
@@ -99,7 +99,838 @@ fn priority() {
 	same!("a^b*c^d", "(a^b)*(c^d)");
 }
 
+fn expr(s: &str) -> fopply::expr::Expression { clear_parsing_info(parser::expr_normalized(s).unwrap()) }
+
+#[test]
+fn unification() {
+	let left = expr("(a+b)+c");
+	let right = expr("x+(y*z)");
+	let unifier = unify(&left, &right).unwrap();
+	assert_eq!(unifier.substitute(&left), expr("(a+b)+(y*z)"));
+	assert_eq!(unifier.substitute(&right), expr("(a+b)+(y*z)"));
+
+	let renamed = rename_patterns(&left, "'");
+	assert!(unify(&left, &renamed).is_ok());
+
+	assert!(matches!(unify(&expr("a"), &expr("a+b")), Err(UnifyError::Occurs { .. })));
+	assert!(matches!(unify(&expr("a+b"), &expr("a*b")), Err(UnifyError::Clash { .. })));
+	assert!(matches!(unify(&expr("f(a, a)"), &expr("f(1, 2)")), Err(UnifyError::Clash { .. })));
+
+	let unifier = unify(&expr("f(a, b, a)"), &expr("f(b, c, g(c))")).unwrap_err();
+	assert!(matches!(unifier, UnifyError::Occurs { .. }));
+
+	let unifier = unify(&expr("f(a, b)"), &expr("f(b, g(c))")).unwrap();
+	assert_eq!(unifier.get("a"), Some(&expr("g(c)")));
+	assert_eq!(unifier.get("b"), Some(&expr("g(c)")));
+}
+
+#[test]
+fn match_error() {
+	let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::default());
+	let mut bindings = BindingStorage::default();
+	let error = find_bindings(expr("(x+y)*z"), &expr("(a+b)*a"), &mut bindings, &mut any_function_bindings).unwrap_err();
+	assert_eq!(error.position.0, vec![1]);
+	match error.kind {
+		MatchErrorKind::Conflict(BindingConflict { pattern_name, bound, actual }) => {
+			assert_eq!(pattern_name, "a");
+			assert_eq!(bound, expr("x"));
+			assert_eq!(actual, expr("z"));
+		},
+		kind => panic!("wrong error: {:?}", kind),
+	}
+
+	let error = find_bindings(expr("(x+y)*z"), &expr("(a-b)*c"), &mut BindingStorage::default(), &mut any_function_bindings)
+		.unwrap_err();
+	assert_eq!(error.position.0, vec![0]);
+	assert!(matches!(error.kind, MatchErrorKind::Mismatch { .. }));
+
+	let input = "[sub]\n1. a*(b-c) <-> a*(c-b);\n\n[test]\n1. x*(y+z) <-> x*(z+y) {\n    x*(y+z);\n    ^^^^^^^ sub.1l;\n};\n";
+	let math = parser::math(input).unwrap();
+	let formulas = fopply::proof::read_math(&math).unwrap();
+	let errors = fopply::proof::is_proofs_correct(&math, &formulas, 1).unwrap_err();
+	assert_eq!(errors.len(), 1);
+	assert!(matches!(errors[0].error.inner, fopply::proof::ProofError::CannotFindBindings(_)));
+	assert_eq!(&input[errors[0].error.span.0.clone()], "y+z");
+
+	// Ошибка внутри значения переменной функции указывает туда, где эта переменная стоит в выражении.
+	let (key, value) = parser::function_binding("$f(x) := 2*x").unwrap();
+	let mut any_function_bindings = ManualAnyFunctionBinding::new(vec![(key, value)].into_iter().collect());
+	let error =
+		find_bindings(expr("2*((y+z)*w)"), &expr("$f((a-b)*c)"), &mut BindingStorage::default(), &mut any_function_bindings)
+			.unwrap_err();
+	assert_eq!(error.position.0, vec![1, 0]);
+	assert!(matches!(error.kind, MatchErrorKind::Mismatch { .. }));
+}
+
+#[test]
+fn duplicate_bindings() {
+	use fopply::proof::ProofError;
+
+	let library = "[part]\n1. $f(part(cond, then, else)) <-> part(cond, $f(then), $f(else));\n2. part(x, a, a) <-> a;\n\n[test]\n";
+	let input = format!("{}1. a <-> part(c, a, a) {{\n    a;\n    ^ part.2r x := c, x := d;\n}};\n", library);
+	let errors = check_proofs(&input).unwrap_err();
+	assert!(matches!(&errors[0].error.inner, ProofError::BindingRedefined { name } if name == "x"));
+
+	let input = format!(
+		"{}1. 1 + part(c, a, b) <-> part(c, 1 + a, 1 + b) {{\n    1 + part(c, a, b);\n    ^^^^^^^^^^^^^^^^^ part.1l $f(x) := 1+x, $f(y) := 2+y;\n}};\n",
+		library
+	);
+	let errors = check_proofs(&input).unwrap_err();
+	assert!(matches!(&errors[0].error.inner, ProofError::FunctionBindingRedefined { name, .. } if name == "f"));
+	assert_eq!(&input[errors[0].error.span.0.clone()], "$f(y) := 2+y");
+	assert_eq!(&input[errors[0].notes[1].span.0.clone()], "$f(x) := 1+x");
+}
+
+#[test]
+fn apply_error() {
+	let bindings = BindingStorage::default();
+	let any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::default());
+	assert_eq!(
+		apply_bindings(expr("1 + $f(a)"), &bindings, &any_function_bindings),
+		Err(ApplyError::FunctionBindingNotFound { name: "f".to_string() })
+	);
+
+	let (key, value) = parser::function_binding("$f(x) := a*x").unwrap();
+	let any_function_bindings = ManualAnyFunctionBinding::new(vec![(key, value)].into_iter().collect());
+	assert_eq!(
+		apply_bindings(expr("1 + $f(a)"), &bindings, &any_function_bindings),
+		Err(ApplyError::FunctionNotMatched { name: "f".to_string() })
+	);
+}
+
+fn check_proofs(input: &str) -> Result<(), Vec<fopply::proof::ProofStepError>> {
+	let math = parser::math(input).unwrap();
+	let formulas = fopply::proof::read_math(&math).unwrap();
+	fopply::proof::is_proofs_correct(&math, &formulas, 0)
+}
+
+#[test]
+fn metavariables() {
+	let library = "[part]\n1. part(x, a, a) <-> a;\n[mul]\n1. a*1 <-> a;\n";
+	assert!(check_proofs(&format!(
+		"{}[test]\n1. a <-> part(b = 0, a, a*1) {{\n    a;\n    ^ part.1r;\n    part(?x, a, a);\n    .           ^ mul.1r;\n}};\n",
+		library
+	))
+	.is_ok());
+	assert!(check_proofs(&format!(
+		"{}[test]\n1. a <-> part(b = 0, a*1, a) {{\n    a;\n    ^ part.1r;\n    part(b = 0, a, a);\n    .           ^ mul.1r;\n}};\n",
+		library
+	))
+	.is_ok());
+	assert!(check_proofs(&format!(
+		"{}[test]\n1. a <-> part(b = 0, a, a) {{\n    a;\n    ^ part.1r;\n    part(b = 0, c, a);\n    .           ^ mul.1r;\n}};\n",
+		library
+	))
+	.is_err());
+}
+
+#[test]
+fn metavariables_in_formulas() {
+	use fopply::{
+		binding::FormulaError,
+		proof::{read_math, ProofError, ReadMathError},
+	};
+
+	let input = "[a]\n1. x+0 <-> x;\n2. x <-> ?y {\n    x;\n    ^ a.1r;\n};\n\n[b]\n1. 1 <-> 2 {\n    1;\n    ^ a.2l;\n    ?y;\n    ^^ a.2l;\n    2;\n    ^ a.1r;\n    2+0;\n    ^^^ a.1l;\n};\n";
+	let errors = read_math(&parser::math(input).unwrap()).unwrap_err();
+	assert!(matches!(
+		&errors[..],
+		[error] if matches!(&error.inner, ReadMathError::FormulaError(FormulaError::MetavariablesInFormula { names }) if names == &["?y"])
+	));
+	assert!(read_math(&parser::math("[a]\n1. x <-> ?y |- x <-> x;\n").unwrap()).is_err());
+
+	let library = "[a]\n1. x+0 <-> x;\n\n[test]\n";
+	let errors = check_proofs(&format!("{}1. x <-> x {{\n    have wrong: a <-> ?b {{\n        a;\n        ^ a.1r;\n    }};\n    x;\n    ^ a.1r;\n    x+0;\n    ^^^ a.1l;\n}};\n", library)).unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::FormulaError(FormulaError::MetavariablesInFormula { .. })));
+
+	// `?y`, который проверка не создавала, это обычный паттерн, а не метапеременная.
+	let errors = check_proofs(&format!("{}1. 1 <-> 1+0+0 {{\n    1;\n    ^ a.1r;\n    ?y;\n    ^^ a.1r;\n}};\n", library)).unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::StepWrong { .. }));
+}
+
+#[test]
+fn all_proof_errors() {
+	use fopply::proof::ProofError;
+
+	let input = "[sum]\n1. a+b <-> b+a;\n2. a+0 <-> a;\n\n[test]\n1. (x+0)+(y+0) <-> y+x {\n    (x+0)+(y+0);\n    ^^^^^^^^^^^ sum.7l;\n    (x+0)+(y+0);\n    .      ^^^ sum.2l;\n    (x+0)+z;\n    ^^^^^^^ sum.1l;\n    y+(x+0);\n    .  ^^^ sum.2l;\n};\n";
+	let errors = check_proofs(input).unwrap_err();
+	let kinds: Vec<_> = errors.iter().map(|error| &error.error.inner).collect();
+	assert!(matches!(kinds[..], [ProofError::FormulaNotFound, ProofError::StepWrong { .. }, ProofError::StepWrong { .. }]));
+	assert_eq!(&input[errors[0].error.span.0.clone()], "sum.7l");
+	assert_eq!(&input[errors[1].error.span.0.clone()], "(x+0)+z");
+	assert_eq!(&input[errors[2].error.span.0.clone()], "y+(x+0)");
+	assert_eq!(&input[errors[0].notes[0].span.0.clone()], "(x+0)+(y+0)");
+	assert_eq!(&input[errors[1].notes[0].span.0.clone()], "sum.2l");
+	assert!(errors[1].notes[0].inner.contains("step 2"));
+}
+
+#[test]
+fn binding_diagnostics() {
+	use fopply::{expr::AnyFunctionNames, proof::ProofError};
+
+	let input = "[part]\n1. $f(part(cond, then, else)) <-> part(cond, $f(then), $f(else));\n2. part(x, a, a) <-> a;\n\n[test]\n1. 1 + part(c, a, b) <-> part(c, 1 + a, 1 + b) {\n    1 + part(c, a, b);\n    ^^^^^^^^^^^^^^^^^ part.1l $g(x, y) := x;\n};\n2. a <-> part(c, a, a) {\n    a;\n    ^ part.2r x := c, a := a;\n};\n";
+	let errors = check_proofs(input).unwrap_err();
+	assert_eq!(errors.len(), 2);
+	match &errors[0].error.inner {
+		ProofError::WrongFunctionBindings { missing, extra } => {
+			assert_eq!(missing, &vec![AnyFunctionNames { name: "f".to_string(), arguments_count: 1 }]);
+			assert_eq!(extra, &vec![AnyFunctionNames { name: "g".to_string(), arguments_count: 2 }]);
+		},
+		error => panic!("wrong error: {:?}", error),
+	}
+	match &errors[1].error.inner {
+		ProofError::ExtraBindingsProvided { extra, functions } => {
+			assert_eq!(extra, &vec!["a".to_string()]);
+			assert!(functions.is_empty());
+		},
+		error => panic!("wrong error: {:?}", error),
+	}
+	assert!(errors[0].error.inner.to_string().contains("missing [$f(_)], superfluous [$g(_, _)]"));
+
+	let suggested = input.replace(" $g(x, y) := x", "");
+	let errors = check_proofs(&suggested).unwrap_err();
+	assert_eq!(errors[0].notes[1].inner, "try `$f(x) := (1+x)`");
+
+	// Биндинг функции, записанный как биндинг паттерна.
+	let errors = check_proofs(&input.replace(" $g(x, y) := x", " f := 1+x")).unwrap_err();
+	match &errors[0].error.inner {
+		ProofError::ExtraBindingsProvided { extra, functions } => {
+			assert_eq!(extra, &vec!["f".to_string()]);
+			assert_eq!(functions, &vec![AnyFunctionNames { name: "f".to_string(), arguments_count: 1 }]);
+		},
+		error => panic!("wrong error: {:?}", error),
+	}
+	assert!(errors[0].error.inner.to_string().contains("[$f(_)] are functions"));
+
+	// Для функции двух аргументов биндинг не подбирается, и ошибка об этом говорит.
+	let input = "[swap]\n1. $f(a, b) <-> $f(b, a);\n\n[test]\n1. 1+2 <-> 2+1 {\n    1+2;\n    ^^^ swap.1l;\n};\n";
+	let errors = check_proofs(input).unwrap_err();
+	assert!(errors[0].error.inner.to_string().contains("missing [$f(_, _)]"));
+	assert!(errors[0].notes[1].inner.contains("only when one function of one argument is missing"));
+}
+
+#[test]
+fn dependency_cycles() {
+	use fopply::proof::{proofs_has_cycles, DependencyError, FormulaPosition};
+
+	let input = "[a]\n1. x <-> x {\n    x;\n    ^ b.1l;\n};\n2. x <-> x {\n    x;\n    ^ c.1l;\n};\n\n[b]\n1. x <-> x {\n    x;\n    ^ a.1l;\n};\n";
+	let errors = proofs_has_cycles(&parser::math(input).unwrap()).unwrap_err();
+	assert_eq!(errors.len(), 2);
+	match &errors[0].error.inner {
+		DependencyError::FormulaNotFound(position) => assert_eq!(position.to_string(), "c.1"),
+		error => panic!("wrong error: {:?}", error),
+	}
+	match &errors[1].error.inner {
+		DependencyError::Cycle { cycle } => {
+			let cycle: Vec<_> = cycle.iter().map(FormulaPosition::to_string).collect();
+			assert!(cycle == ["a.1", "b.1"] || cycle == ["b.1", "a.1"]);
+		},
+		error => panic!("wrong error: {:?}", error),
+	}
+	assert_eq!(errors[1].notes.len(), 1);
+	let mut spans = vec![&input[errors[1].error.span.0.clone()], &input[errors[1].notes[0].span.0.clone()]];
+	spans.sort();
+	assert_eq!(spans, ["a.1l", "b.1l"]);
+
+	let input = "[a]\n1. x <-> x {\n    x;\n    ^ a.1l;\n};\n";
+	let errors = proofs_has_cycles(&parser::math(input).unwrap()).unwrap_err();
+	assert_eq!(errors[0].error.inner.to_string(), "proofs have a cycle: a.1 -> a.1");
+}
+
+#[test]
+fn hash_consing() {
+	use fopply::dag::*;
+
+	let mut store = ExprStore::default();
+	let expression = expr("(a+b)*(a+b) + part(c, a+b, 1)");
+	let id = store.intern(&expression);
+	assert_eq!(store.to_expression(id), expression);
+	assert_eq!(store.len(), 8);
+	assert_eq!(store.size(id), 14);
+
+	let mut other = ExprStore::default();
+	other.intern(&expr("part(c, 1, 1)"));
+	let other_id = other.intern(&expression);
+	assert_eq!(store.hash(id), other.hash(other_id));
+	assert_eq!(store.intern(&expr("a+b")), store.get(id, fopply::expr::ExprPosition::from_slice(&[0, 1])).unwrap());
+
+	let expression = store.intern(&expr("part(not(b = 0), a*part($true, 1, $undefined), a)"));
+	let left = store.intern(&expr("part(cond, $f(part(cond2, then2, else2)), else)"));
+	let right = store.intern(&expr("part(cond, $f(part(cond2 & cond, then2, else2)), else)"));
+	let (key, value) = parser::function_binding("$f(x) := a*x").unwrap();
+	let key = store.symbol(&key);
+	let value = store.intern_function_pattern(&value);
+	let mut functions = NodeFunctionBindings::new(vec![(key, value)].into_iter().collect());
+	let mut bindings = NodeBindings::default();
+	store.find_bindings(expression, left, &mut bindings, &mut functions).unwrap();
+	let result = store.apply_bindings(right, &bindings, &functions).unwrap();
+	assert_eq!(store.to_expression(result), expr("part(not(b = 0), a*part($true & not(b = 0), 1, $undefined), a)"));
+}
+
+#[test]
+fn formula_index() {
+	use fopply::{index::*, proof::FormulaPosition};
+
+	let math = std::fs::read_to_string("fpl/math.fpl").unwrap();
+	let formulas = fopply::proof::read_math(&parser::math(&math).unwrap()).unwrap();
+	let index = FormulaIndex::new(&formulas);
+	let rule = |module_name: &str, position: usize, left_to_right: bool| IndexedRule {
+		formula: FormulaPosition { module_name: module_name.to_string(), position },
+		left_to_right,
+	};
+
+	let candidates = index.candidates(&expr("x+0"));
+	assert!(candidates.contains(&rule("sum", 3, true)));
+	assert!(candidates.contains(&rule("sum", 2, true)));
+	assert!(candidates.contains(&rule("unsafe", 1, true)));
+	assert!(!candidates.contains(&rule("sum", 1, true)));
+	assert!(!candidates.contains(&rule("mul", 3, true)));
+
+	// Каждая формула, которая действительно применяется, должна быть среди кандидатов.
+	let expression = expr("(x+y)+0");
+	let candidates = index.candidates(&expression);
+	for (position, formula) in &formulas {
+		let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::default());
+		let mut bindings = BindingStorage::default();
+		if find_bindings(expression.clone(), &formula.left.pattern, &mut bindings, &mut any_function_bindings).is_ok() {
+			assert!(candidates.contains(&rule(&position.module_name, position.position, true)));
+		}
+	}
+	assert!(candidates.contains(&rule("sum", 1, true)));
+
+	let everywhere = index.candidates_everywhere(&expression);
+	assert!(everywhere.contains(&(fopply::expr::ExprPositionOwned(vec![0]), rule("sum", 2, true))));
+}
+
+#[test]
+fn dependency_export() {
+	use fopply::{
+		export::*,
+		proof::{read_math, FormulaPosition},
+	};
+
+	let input = "[a]\n1. x <-> x+0;\n2. x <-> x+0+0 {\n    x;\n    ^ a.1l;\n    x+0;\n    ^ a.1l;\n};\n\n[b]\n1. x <-> x {\n    x;\n    ^ c.1l;\n};\n\n[s]\n1. a+0 <-> a;\n2. a+b <-> b+a;\n3. (x+0)+0 <-> x {\n    (x+0)+0;\n    simp using [s];\n};\n";
+	let math = parser::math(input).unwrap();
+	let summary = DependencySummary::new(&math, &read_math(&math).unwrap());
+	let position = |module_name: &str, position: usize| FormulaPosition { module_name: module_name.to_string(), position };
+
+	assert_eq!(summary.nodes[&position("a", 1)], FormulaKind::Axiom);
+	assert_eq!(summary.nodes[&position("a", 2)], FormulaKind::Proved);
+	assert_eq!(summary.nodes[&position("c", 1)], FormulaKind::Missing);
+	assert_eq!(summary.edges[&(position("a", 2), position("a", 1))], 2);
+	assert_eq!(summary.edges[&(position("b", 1), position("c", 1))], 1);
+	// `simp` считается шагами, в которые он развернулся, а не всеми формулами модуля.
+	assert_eq!(summary.edges[&(position("s", 3), position("s", 1))], 2);
+	assert!(!summary.edges.contains_key(&(position("s", 3), position("s", 2))));
+
+	assert!(summary.to_dot().contains("\"a.2\" -> \"a.1\" [weight = 2"));
+	assert!(summary.to_json().contains("{\"from\": \"a.2\", \"to\": \"a.1\", \"weight\": 2}"));
+	assert!(summary.to_json().contains("\"kind\": \"axiom\""));
+	assert!(!summary.to_dot().contains("kind"));
+}
+
+#[test]
+fn proof_cache() {
+	use std::cell::Cell;
+
+	use fopply::{cache::ProofCache, infer::infer_steps_where, proof::read_math};
+
+	// Сколько доказательств прошло через вывод шагов.
+	let inferred = Cell::new(0);
+	let check = |input: &str, cache: &mut ProofCache| {
+		let mut math = parser::math(input).unwrap();
+		let formulas = read_math(&math).unwrap();
+		let keys = ProofCache::keys(input, &math, &formulas);
+		infer_steps_where(&mut math, &formulas, |position| {
+			let uncached = !cache.contains(keys[position]);
+			inferred.set(inferred.get() + uncached as usize);
+			uncached
+		})
+		.unwrap();
+		cache.is_proofs_correct(&keys, &math, &formulas, 0)
+	};
+
+	let input = "[a]\n1. x <-> x+0;\n2. x <-> x+0+0 {\n    x;\n    ^ a.1l;\n    x+0;\n    ^ a.1l;\n};\n3. x <-> x {\n    x;\n    ^ a.1l;\n    x+0;\n    ^^^ a.1r;\n};\n";
+	let mut cache = ProofCache::default();
+	check(input, &mut cache).unwrap();
+	assert_eq!((cache.checked, cache.len()), (2, 2));
+	check(input, &mut cache).unwrap();
+	assert_eq!(cache.checked, 0);
+
+	// Изменилось только одно доказательство.
+	let edited = input.replace("    x+0;\n    ^^^ a.1r;", "  x+0;\n  ^^^ a.1r;");
+	check(&edited, &mut cache).unwrap();
+	assert_eq!(cache.checked, 1);
+
+	// Изменилась используемая формула, поэтому оба доказательства проверяются заново.
+	let edited = edited.replace("1. x <-> x+0;", "1. x <-> 0+x;");
+	assert!(check(&edited, &mut cache).is_err());
+	assert_eq!((cache.checked, cache.len()), (2, 0));
+
+	let path = std::env::temp_dir().join("fopply_proof_cache_test.cache");
+	check(input, &mut cache).unwrap();
+	cache.save(&path).unwrap();
+	let mut loaded = ProofCache::load(&path);
+	check(input, &mut loaded).unwrap();
+	assert_eq!(loaded.checked, 0);
+	std::fs::remove_file(&path).unwrap();
+
+	// Доказательство с выводимым шагом берётся из кэша без вывода, пока файл не изменился.
+	let input = "[a]\n1. x+0 <-> x;\n2. (x+0)*y <-> x*y {\n    (x+0)*y;\n};\n";
+	let mut cache = ProofCache::default();
+	check(input, &mut cache).unwrap();
+	assert_eq!((cache.checked, inferred.get()), (1, 1));
+	check(input, &mut cache).unwrap();
+	assert_eq!((cache.checked, inferred.get()), (0, 1));
+	check(&format!("{}\n", input), &mut cache).unwrap();
+	assert_eq!((cache.checked, inferred.get()), (1, 2));
+}
+
+#[test]
+fn parallel_checking() {
+	use fopply::proof::{is_proofs_correct, read_math};
+
+	// Ломаем доказательства в разных местах файла.
+	let input = std::fs::read_to_string("fpl/math.fpl").unwrap().replace("mul.2l;", "mul.99l;");
+	let math = parser::math(&input).unwrap();
+	let formulas = read_math(&math).unwrap();
+
+	let spans = |threads| {
+		let errors = is_proofs_correct(&math, &formulas, threads).unwrap_err();
+		errors.into_iter().map(|error| error.error.span.0).collect::<Vec<_>>()
+	};
+	let sequential = spans(1);
+	assert!(sequential.len() > 1);
+	assert!(sequential.windows(2).all(|x| x[0].start < x[1].start));
+	for threads in [2, 3, 8, 0] {
+		assert_eq!(spans(threads), sequential);
+	}
+}
+
+#[test]
+fn proof_chains() {
+	use fopply::proof::ProofError;
+
+	let axioms = "[sum]\n1. a+0 <-> a;\n2. a+b <-> b+a;\n\n[test]\n";
+	let check = |proofs: &str| check_proofs(&format!("{}{}", axioms, proofs));
+
+	check("1. x <-> x+0 right {\n    x+0;\n    ^^^ sum.1l;\n};\n").unwrap();
+	check("1. x+0 <-> 0+x left {\n    x+0;\n    ^^^ sum.1l;\n} right {\n    0+x;\n    ^^^ sum.2l;\n    x+0;\n    ^^^ sum.1l;\n};\n").unwrap();
+
+	let errors = check("1. 0+x <-> x right {\n    x;\n    ^ sum.1r;\n};\n").unwrap_err();
+	assert_eq!(errors.len(), 1);
+	assert!(matches!(errors[0].error.inner, ProofError::LatestStepWrong { .. }));
+	assert_eq!(errors[0].error.inner.to_string(), "result of latest step is not equal to left side of formula, actual is (x+0)");
+
+	let input = format!("{}1. x+0 <-> 0+x left {{\n    x+0;\n    ^^^ sum.1l;\n}} right {{\n    0+x;\n    ^^^ sum.2l;\n}};\n", axioms);
+	let errors = check_proofs(&input).unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::ChainsDontMeet { .. }));
+	assert!(input[errors[0].error.span.0.clone()].starts_with("right {"));
+	assert_eq!(errors[0].notes.len(), 2);
+
+	assert!(parser::math(&format!("{}1. x <-> x right {{\n    x;\n    ^ sum.1r;\n}} left {{\n    x;\n    ^ sum.1r;\n}};\n", axioms)).is_err());
+}
+
+#[test]
+fn hypotheses() {
+	use fopply::proof::ProofError;
+
+	let axioms = "[part]\n1. part($true, then, else) <-> then;\n\n[eq]\n1. a = a <-> $true;\n\n[test]\n1. cond <-> $true |- part(cond, then, else) <-> then {\n    part(cond, then, else);\n    .    ^^^^ hyp.1l;\n    part($true, then, else);\n    ^^^^^^^^^^^^^^^^^^^^^^^ part.1l;\n};\n";
+	let check = |proofs: &str| check_proofs(&format!("{}{}", axioms, proofs));
+
+	check("2. x = 0 <-> $true |- part(x = 0, a, b) <-> a {\n    part(x = 0, a, b);\n    ^^^^^^^^^^^^^^^^^ test.1l;\n};\n").unwrap();
+	check("2. part(y = y, a, b) <-> a {\n    part(y = y, a, b);\n    ^^^^^^^^^^^^^^^^^ test.1l by eq.1l;\n};\n").unwrap();
+
+	let errors = check("2. part(y = 0, a, b) <-> a {\n    part(y = 0, a, b);\n    ^^^^^^^^^^^^^^^^^ test.1l by eq.1l;\n};\n").unwrap_err();
+	assert_eq!(errors.len(), 1);
+	assert_eq!(
+		errors[0].error.inner.to_string(),
+		"hypothesis `(y=0) <-> $true` of this formula is not proved, it should be a hypothesis of current theorem or be proved by formula after `by`"
+	);
+
+	// Условие относится только к паттернам теоремы, а не к любому выражению.
+	let errors = check("2. c <-> $true |- part(d, a, c) <-> a {\n    part(d, a, c);\n    .    ^ hyp.1l;\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::CannotFindBindings(_)));
+	let errors = check("2. c <-> $true |- c <-> c {\n    c;\n    ^ hyp.2l;\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::FormulaNotFound));
+
+	let math = parser::math(&format!("{}2. z <-> $true |- a <-> a;\n", axioms)).unwrap();
+	assert!(fopply::proof::read_math(&math).is_err());
+}
+
+#[test]
+fn lemmas() {
+	use fopply::proof::{dependency_graph, proofs_has_cycles, ProofError};
+
+	let axioms = "[sum]\n1. a+0 <-> a;\n2. a+b <-> b+a;\n\n[test]\n";
+	let lemma = "    have zero: 0+a <-> a {\n        0+a;\n        ^^^ sum.2l;\n        a+0;\n        ^^^ sum.1l;\n    };\n";
+	let check = |proofs: &str| check_proofs(&format!("{}{}", axioms, proofs));
+
+	let input = format!("{}1. (0+x)+(0+y) <-> x+y {{\n{}    (0+x)+(0+y);\n    .^^^ zero.l;\n    x+(0+y);\n    .  ^^^ zero.l;\n}};\n", axioms, lemma);
+	check_proofs(&input).unwrap();
+	let math = parser::math(&input).unwrap();
+	proofs_has_cycles(&math).unwrap();
+	let graph = dependency_graph(&math);
+	assert!(graph.node_indices().all(|node| graph[node].module_name != "zero"));
+	assert_eq!(graph.edge_count(), 2);
+
+	// Лемма видна только после своего определения и только в своём доказательстве.
+	let errors = check(&format!("1. 0+x <-> x {{\n    0+x;\n    ^^^ zero.l;\n{}}};\n2. 0+x <-> x {{\n    0+x;\n    ^^^ zero.l;\n}};\n", lemma)).unwrap_err();
+	assert_eq!(errors.len(), 2);
+	assert!(errors.iter().all(|error| matches!(error.error.inner, ProofError::FormulaNotFound)));
+
+	let errors = check(&format!("1. 0+x <-> x {{\n{}{}    0+x;\n    ^^^ zero.l;\n}};\n", lemma, lemma)).unwrap_err();
+	assert!(matches!(errors[..], [ref error] if matches!(error.error.inner, ProofError::LemmaRedefined)));
+
+	let errors = check("1. 0+x <-> x {\n    have wrong: a <-> a+a {\n        a;\n        ^ sum.1r;\n    };\n    0+x;\n    ^^^ sum.2l;\n    x+0;\n    ^^^ sum.1l;\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::LatestStepWrong { .. }));
+
+	// Номер формулы не может быть нулём, а `hyp` занят условиями теоремы.
+	assert!(parser::formula_position("sum.0l").is_err());
+	assert!(parser::formula_position("hyp.l").is_err());
+	assert!(parser::formula_position("zero.l").unwrap().is_lemma());
+	assert!(!parser::formula_position("sum.1l").unwrap().is_lemma());
+	assert!(parser::math(&format!("{}1. 0+x <-> x {{\n    0+x;\n    ^^^ sum.0l;\n}};\n", axioms)).is_err());
+	let input = format!("{}1. 0+x <-> x {{\n{}    0+x;\n    ^^^ sum.2l;\n}};\n", axioms, lemma.replace("have zero", "have hyp"));
+	assert!(parser::math(&input).is_err());
+}
+
+#[test]
+fn tactics() {
+	use fopply::{proof::ProofError, tactic::TacticError};
+
+	let axioms = "[sum]\n1. a+0 <-> a;\n2. a+b <-> b+a;\n\n[test]\n";
+	let check = |proofs: &str| check_proofs(&format!("{}{}", axioms, proofs));
+
+	check("1. (x+0)+(y+0) <-> x+y {\n    (x+0)+(y+0);\n    repeat sum.1l;\n};\n").unwrap();
+	check("1. (x+0)+(0+y) <-> y+x {\n    (x+0)+(0+y);\n    rewrite [sum.1l, sum.2l];\n    (0+y)+x;\n    .^^^ sum.2l;\n    (y+0)+x;\n    .^^^ sum.1l;\n};\n").unwrap();
+	check("1. (x+0)+(y+0) <-> x+(y+0) {\n    (x+0)+(y+0);\n    repeat sum.1l at @0;\n};\n").unwrap();
+	check("1. (x+0)+(0+0) <-> x {\n    (x+0)+(0+0);\n    simp using [sum];\n};\n").unwrap();
+
+	// Доказываемая формула не используется в `simp`, а другие формулы модуля используются.
+	let errors = check("1. x+0 <-> x {\n    x+0;\n    simp using [test];\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::TacticFailed(TacticError::ModuleNotFound)));
+	check("1. a+0 <-> a {\n    a+0;\n    ^^^ sum.1l;\n};\n2. (x+0)+0 <-> x {\n    (x+0)+0;\n    simp using [test];\n};\n").unwrap();
+
+	let errors = check("1. x+y <-> y+x {\n    x+y;\n    repeat sum.2l;\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::TacticFailed(TacticError::Loop { steps: 2, .. })));
+	let errors = check("1. x <-> x {\n    x;\n    rewrite [sum.1l];\n    x;\n    ^ sum.1r;\n};\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::TacticFailed(TacticError::NotApplicable { .. })));
+	assert!(matches!(errors[1].error.inner, ProofError::LatestStepWrong { .. }));
+	assert!(errors[1].notes[0].inner.contains("step 1"));
+
+	// `simp` берёт из своего модуля только формулы выше, поэтому формула ниже может ссылаться на него без цикла.
+	let input = "[sum]\n1. a+0 <-> a;\n2. a+b <-> b+a;\n3. (x+0)+0 <-> x {\n    (x+0)+0;\n    simp using [sum];\n};\n4. ((x+0)+0)*y <-> x*y {\n    ((x+0)+0)*y;\n    @0 sum.3l;\n};\n";
+	let math = parser::math(input).unwrap();
+	fopply::proof::proofs_has_cycles(&math).unwrap();
+	check_proofs(input).unwrap();
+	let errors = check_proofs("[sum]\n1. (x+0)+0 <-> x {\n    (x+0)+0;\n    simp using [sum];\n};\n2. a+0 <-> a;\n").unwrap_err();
+	assert!(matches!(errors[0].error.inner, ProofError::TacticFailed(TacticError::ModuleNotFound)));
+}
+
+#[test]
+fn proof_search() {
+	use fopply::{
+		print,
+		proof::{read_math, FormulaPosition},
+		search::{prove, SearchError, SearchLimits},
+	};
+
+	for string in ["a-b-c", "a-(b-c)", "(-a)+(-b)", "-a*b", "a^b^c", "(a^b)^c", "a=b&(c|d)", "$f(a+b, -c)/(2*x)"] {
+		let expr = clear_parsing_info(parser::expr_normalized(string).unwrap());
+		assert_eq!(print::to_string(&expr), string);
+	}
+
+	let input = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n1. (0+x)+(y+z) <-> z+(x+y);\n2. x <-> x+1;\n";
+	let math = parser::math(input).unwrap();
+	let formulas = read_math(&math).unwrap();
+	let position = |position| FormulaPosition { module_name: "test".to_string(), position };
+
+	let steps = prove(&math, &formulas, &position(1), &SearchLimits::default()).unwrap();
+	let proof = format!("[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n1. (0+x)+(y+z) <-> z+(x+y) {};\n", print::proof_block(&steps));
+	check_proofs(&proof).unwrap();
+
+	let limits = SearchLimits { max_nodes: 5, timeout: None };
+	assert!(matches!(prove(&math, &formulas, &position(1), &limits), Err(SearchError::NodeLimit { .. })));
+	assert!(matches!(prove(&math, &formulas, &position(2), &SearchLimits::default()), Err(SearchError::Exhausted { .. })));
+}
+
+#[test]
+fn equality_saturation() {
+	use fopply::{
+		egraph::prove,
+		print,
+		proof::{read_math, FormulaPosition},
+		search::{SearchError, SearchLimits},
+	};
+
+	let library = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n";
+	let formulas = "1. (x+y)+(z+w) <-> (w+z)+(y+x);\n2. y = 0 <-> $true |- x+(y = 0) <-> $true+x;\n3. x <-> x+1;\n";
+	let input = format!("{}{}", library, formulas);
+	let math = parser::math(&input).unwrap();
+	let formulas = read_math(&math).unwrap();
+	let position = |position| FormulaPosition { module_name: "test".to_string(), position };
+
+	let steps = prove(&math, &formulas, &position(1), &SearchLimits::default()).unwrap();
+	check_proofs(&format!("{}1. (x+y)+(z+w) <-> (w+z)+(y+x) {};\n", library, print::proof_block(&steps))).unwrap();
+
+	// Условия формулы тоже используются.
+	let steps = prove(&math, &formulas, &position(2), &SearchLimits::default()).unwrap();
+	assert!(steps.iter().any(|step| step.rule.module_name == "hyp"));
+	check_proofs(&format!("{}1. y = 0 <-> $true |- x+(y = 0) <-> $true+x {};\n", library, print::proof_block(&steps)))
+		.unwrap();
+
+	assert!(matches!(prove(&math, &formulas, &position(3), &SearchLimits::default()), Err(SearchError::Exhausted { .. })));
+
+	// `find_bindings` не сопоставляет `a-a` с `x-(x+0)`, а e-граф сопоставляет, потому что `x` и `x+0` в одном классе.
+	let mut bindings = BindingStorage::default();
+	let mut any_function_bindings = ManualAnyFunctionBinding::new(BTreeMap::new());
+	let error = find_bindings(expr("x-(x+0)"), &expr("a-a"), &mut bindings, &mut any_function_bindings).unwrap_err();
+	assert!(matches!(error.kind, MatchErrorKind::Conflict(_)));
+	let library = "[f]\n1. a+0 <-> a;\n2. a-a <-> 0;\n\n[test]\n";
+	let input = format!("{}1. x-(x+0) <-> 0;\n", library);
+	let math = parser::math(&input).unwrap();
+	let formulas = read_math(&math).unwrap();
+	let steps = prove(&math, &formulas, &position(1), &SearchLimits::default()).unwrap();
+	check_proofs(&format!("{}1. x-(x+0) <-> 0 {};\n", library, print::proof_block(&steps))).unwrap();
+}
+
+#[test]
+fn minimize() {
+	use fopply::{
+		minimize::minimize,
+		proof::{read_math, FormulaPosition},
+		search::SearchLimits,
+	};
+
+	let input = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n1. (x+0)+y <-> y+x {
+    (x+0)+y;
+    ^^^^^^^ sum.2l;
+    y+(x+0);
+    ^^^^^^^ sum.2l;
+    (x+0)+y;
+    .^^^ sum.3l;
+    x+y;
+    ^^^ sum.3r;
+    (x+y)+0;
+    .^^^ sum.2l;
+    (y+x)+0;
+    ^^^^^^^ sum.3l;
+};
+";
+	check_proofs(input).unwrap();
+	let math = parser::math(input).unwrap();
+	let formulas = read_math(&math).unwrap();
+	let position = FormulaPosition { module_name: "test".to_string(), position: 1 };
+	let limits = SearchLimits { max_nodes: 2_000, timeout: None };
+
+	// Первые два шага возвращаются к началу, а последние три заменяются одним.
+	let minimized = minimize(input, &math, &formulas, &position, &limits).unwrap();
+	assert_eq!((minimized.before, minimized.after), (6, 2));
+	let span = minimized.span.0;
+	check_proofs(&format!("{}{}{}", &input[..span.start], minimized.text, &input[span.end..])).unwrap();
+
+	// Короткий путь проходит через формулу с `$f`, её биндинг подбирается по выражению.
+	let input = "[mul]\n1. a*b <-> b*a;\n2. a*1 <-> a;\n\n[part]\n1. $f(part(cond, then, else)) <-> part(cond, $f(then), $f(else));\n\n[test]\n1. part(c, 1*a, 1*b) <-> part(c, a, b) {
+    part(c, 1*a, 1*b);
+    .       ^^^ mul.1l;
+    part(c, a*1, 1*b);
+    .       ^^^ mul.2l;
+    part(c, a, 1*b);
+    .          ^^^ mul.1l;
+    part(c, a, b*1);
+    .          ^^^ mul.2l;
+};
+";
+	check_proofs(input).unwrap();
+	let math = parser::math(input).unwrap();
+	let formulas = read_math(&math).unwrap();
+	let position = FormulaPosition { module_name: "test".to_string(), position: 1 };
+	let minimized = minimize(input, &math, &formulas, &position, &limits).unwrap();
+	assert_eq!((minimized.before, minimized.after), (4, 3));
+	assert!(minimized.text.contains("part.1r $f(x) := 1*x"));
+	let span = minimized.span.0;
+	check_proofs(&format!("{}{}{}", &input[..span.start], minimized.text, &input[span.end..])).unwrap();
+}
+
+#[test]
+fn inferred_steps() {
+	use fopply::{
+		infer::{annotate, infer_steps, InferError},
+		proof::{is_proofs_correct, read_math},
+	};
+
+	let library = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n";
+	let infer = |formulas: &str| {
+		let input = format!("{}{}", library, formulas);
+		let mut math = parser::math(&input).unwrap();
+		let global_formulas = read_math(&math).unwrap();
+		infer_steps(&mut math, &global_formulas).map_err(|errors| errors.into_iter().map(|x| x.inner).collect::<Vec<_>>())?;
+		assert!(is_proofs_correct(&math, &global_formulas, 0).is_ok());
+		Ok::<_, Vec<InferError>>(())
+	};
+
+	assert!(infer("1. (x+0)+y <-> y+x {\n    (x+0)+y;\n    x+y;\n};\n").is_ok());
+	assert!(infer("1. (x+y)+z <-> z+(y+x) {\n    (x+y)+z;\n    ? sum.2l;\n    z+(x+y);\n    .  ^^^ ?;\n};\n").is_ok());
+	assert!(infer("1. (0+x)+y <-> x+y {\n    have l: 0+a <-> a {\n        0+a;\n        a+0;\n    };\n    (0+x)+y;\n};\n").is_ok());
+	assert!(matches!(infer("1. (x+0)+0 <-> x+0 {\n    (x+0)+0;\n};\n").unwrap_err()[..], [InferError::Ambiguous { .. }]));
+	assert!(matches!(infer("1. x+y <-> x {\n    x+y;\n};\n").unwrap_err()[..], [InferError::NotFound]));
+
+	let input = format!("{}1. (x+0)+y <-> y+x {{\n\t(x+0)+y; ?;\n\tx+y;\n}};\n", library);
+	let mut math = parser::math(&input).unwrap();
+	let global_formulas = read_math(&math).unwrap();
+	let (output, count) = annotate(&input, &mut math, &global_formulas).unwrap();
+	assert_eq!(count, 2);
+	assert!(output.ends_with("{\n\t(x+0)+y;\n\t.^^^ sum.3l;\n\tx+y;\n\t^^^ sum.2l;\n};\n"));
+	assert!(check_proofs(&output).is_ok());
+}
+
+#[test]
+fn computed_steps() {
+	use fopply::{materialize::materialize, proof::read_math};
+
+	let library = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n\n[test]\n";
+	let input = format!("{}1. (x+0)+y <-> y+x {{\n\t_; @0 sum.3l;\n\t_;\n\t@ sum.2l;\n}};\n", library);
+	assert!(check_proofs(&input).is_ok());
+	assert!(check_proofs(&format!("{}1. (x+0)+y <-> y+x {{\n\t_; @1 sum.3l;\n\t_; @ sum.2l;\n}};\n", library)).is_err());
+	assert!(check_proofs(&format!("{}1. (x+0)+y <-> y+x {{\n\t_; .^^^ sum.3l;\n\t_; @ sum.2l;\n}};\n", library)).is_err());
+
+	let math = parser::math(&input).unwrap();
+	let global_formulas = read_math(&math).unwrap();
+	let (output, count) = materialize(&input, &math, &global_formulas).unwrap();
+	assert_eq!(count, 2);
+	assert!(output.ends_with("{\n\tx+0+y; @0 sum.3l;\n\tx+y;\n\t@ sum.2l;\n};\n"));
+	assert!(check_proofs(&output).is_ok());
+}
+
+#[test]
+fn completion() {
+	use fopply::{completion::*, proof::read_math};
+
+	let expr = |text: &str| clear_parsing_info(parser::expr_normalized(text).unwrap());
+	let complete_modules = |input: &str, modules: &[&str], order: &TermOrder| {
+		let formulas = read_math(&parser::math(input).unwrap()).unwrap();
+		let modules: Vec<String> = modules.iter().map(|x| x.to_string()).collect();
+		let (equations, _) = module_equations(&formulas, &modules);
+		let equations = equations.into_iter().map(|(_, left, right)| (left, right)).collect();
+		complete(equations, order, &CompletionLimits::default())
+	};
+
+	let group = "[group]\n1. 0+x <-> x;\n2. (-x)+x <-> 0;\n3. (x+y)+z <-> x+(y+z);\n";
+	let lpo = TermOrder::Lpo { precedence: vec!["negative".to_string(), "+".to_string(), "0".to_string()] };
+	let rules = complete_modules(group, &["group"], &lpo).unwrap();
+	assert_eq!(rules.len(), 10);
+	assert!(rules.contains(&Rule { left: expr("-(a+b)"), right: expr("(-b)+(-a)") }));
+	assert!(rules.contains(&Rule { left: expr("a+((-a)+b)"), right: expr("b") }));
+	assert!(rules.iter().all(|rule| lpo.greater(&rule.left, &rule.right)));
+
+	let sum = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n";
+	let kbo = TermOrder::Kbo { precedence: Vec::new(), weights: BTreeMap::new() };
+	match complete_modules(sum, &["sum"], &kbo) {
+		Err(CompletionError::Unorientable { equations, .. }) => assert!(equations.contains(&(expr("a+b"), expr("b+a")))),
+		other => panic!("{:?}", other),
+	}
+}
+
+#[test]
+fn analyze() {
+	use fopply::{
+		analyze::{analyze, Issue},
+		completion::TermOrder,
+		proof::{read_math, FormulaPosition},
+	};
+
+	let input = "[group]\n1. 0+x <-> x;\n2. (-x)+x <-> 0;\n3. x+(y+z) <-> (x+y)+z;\n\n[unsafe]\n1. a <-> b;\n";
+	let formulas = read_math(&parser::math(input).unwrap()).unwrap();
+	let order = TermOrder::Lpo { precedence: vec!["negative".to_string(), "+".to_string(), "0".to_string()] };
+	let reversed = [FormulaPosition { module_name: "group".to_string(), position: 3 }];
+
+	let analysis = analyze(&formulas, &["group".to_string()], &[], &order, 1000);
+	assert!(!analysis.is_terminating());
+	assert!(matches!(&analysis.issues[..], [Issue::NotDecreasing { rule, .. }, ..] if rule.to_string() == "group.3l"));
+
+	let analysis = analyze(&formulas, &["group".to_string()], &reversed, &order, 1000);
+	assert!(analysis.is_terminating());
+	assert!(!analysis.is_locally_confluent());
+	assert!(analysis.issues.iter().all(|issue| matches!(issue, Issue::NotJoinable { .. })));
+
+	let analysis = analyze(&formulas, &["unsafe".to_string()], &[], &order, 1000);
+	assert!(matches!(&analysis.issues[..], [Issue::BarePattern { .. }, Issue::NotDecreasing { .. }]));
+
+	// С коммутативностью у сторон нет нормальных форм, но пары всё равно сводятся за несколько шагов.
+	let input = "[sum]\n1. (a+b)+c <-> a+(b+c);\n2. a+b <-> b+a;\n3. a+0 <-> a;\n";
+	let formulas = read_math(&parser::math(input).unwrap()).unwrap();
+	let analysis = analyze(&formulas, &["sum".to_string()], &[], &order, 1000);
+	assert!(!analysis.is_terminating());
+	assert!(analysis.critical_pairs > 0);
+	assert!(analysis.is_locally_confluent());
+}
+
+#[test]
+fn quickcheck() {
+	use fopply::{
+		proof::read_math,
+		eval::Interpretations,
+		quickcheck::{quickcheck, Outcome},
+	};
+
+	let outcomes = |input: &str| {
+		quickcheck(&read_math(&parser::math(input).unwrap()).unwrap(), &Interpretations::default(), 100, 1)
+	};
+
+	let input = "[div]\n1. a/a <-> part(a != 0, 1, $undefined);\n2. a/0 <-> $undefined;\n3. a <-> part(b = 0, a, a*(b/b));\n\
+	             [eq]\n1. a = a <-> $true;\n[abs]\n1. abs(x) <-> part(x >= 0, x, -x);\n\
+	             [part]\n1. cond <-> $true |- part(cond, then, else) <-> then;\n";
+	assert!(outcomes(input).iter().all(|(_, outcome)| matches!(outcome, Outcome::Passed)));
+
+	let outcomes = outcomes("[unsafe]\n1. a <-> b;\n2. a*0 <-> a;\n3. brackets(a) <-> a;\n");
+	assert!(matches!(&outcomes[..], [(_, Outcome::Failed(_)), (_, Outcome::Failed(_)), (_, Outcome::Skipped(_))]));
+}
+
+#[test]
+fn eval() {
+	use fopply::{
+		eval::{eval, EvalError, Interpretations, Rational, Value},
+		parsing::clear_parsing_info,
+	};
+
+	let expr = |text: &str| clear_parsing_info(parser::expr_normalized(text).unwrap());
+	let number = |numerator, denominator| Value::Number(Rational::new(numerator, denominator).unwrap());
+	let mut interpretations = Interpretations::default();
+	let values = vec![("a".to_string(), number(1, 2)), ("b".to_string(), number(0, 1))].into_iter().collect();
+	let eval = |text: &str, interpretations: &Interpretations| eval(&expr(text), &values, interpretations);
+
+	assert_eq!(eval("a*(b+3) - (-a)", &interpretations), Ok(number(2, 1)));
+	assert_eq!(eval("1/b", &interpretations), Ok(Value::Undefined));
+	assert_eq!(eval("(1/b)*0 + a", &interpretations), Ok(Value::Undefined));
+	assert_eq!(eval("part(b != 0, 1/b, a)", &interpretations), Ok(number(1, 2)));
+	assert_eq!(eval("b/b = part(b != 0, 1, $undefined)", &interpretations), Ok(Value::Bool(true)));
+	assert_eq!(eval("a < 1 & not($false)", &interpretations), Ok(Value::Bool(true)));
+	assert_eq!(eval("$true + 1", &interpretations), Ok(Value::Undefined));
+
+	let unknown = EvalError::UnknownFunction { name: "double".to_string(), arguments_count: 1 };
+	assert_eq!(eval("double(a)", &interpretations), Err(unknown));
+	interpretations.insert("double", 1, |args| match args {
+		[Value::Number(a)] => Ok(Value::Number(a.checked_add(a).ok_or(EvalError::Overflow)?)),
+		_ => Ok(Value::Undefined),
+	});
+	assert_eq!(eval("double(a) + double(1/b)", &interpretations), Ok(Value::Undefined));
+	assert_eq!(eval("double(a)", &interpretations), Ok(number(1, 1)));
+
+	// `min` это `i128::MIN`, у него нет противоположного числа.
+	let min = "(-4611686018427387904)*4611686018427387904*8";
+	assert_eq!(eval(min, &interpretations), Ok(Value::Number(Rational::integer(i128::MIN))));
+	assert_eq!(eval(&format!("{}/(-1)", min), &interpretations), Err(EvalError::Overflow));
+	assert_eq!(eval(&format!("({})/({})", min, min), &interpretations), Err(EvalError::Overflow));
+	assert_eq!(eval(&format!("abs({})", min), &interpretations), Err(EvalError::Overflow));
+	assert_eq!(eval(&format!("{}*2", min), &interpretations), Err(EvalError::Overflow));
+	assert_eq!(eval(&format!("({}+1)/(-1)", min), &interpretations), Ok(Value::Number(Rational::integer(i128::MAX))));
+	assert_eq!(Rational::new(i128::MIN, -1), None);
+	assert_eq!(Rational::new(i128::MIN, 2), Some(Rational::integer(i128::MIN / 2)));
+}
+
 // #[test]
+#[allow(dead_code)]
 fn parsing_info() {
 	macro_rules! debug_unwrap {
 		($name:ident( $($arg:expr),* )) => {{
@@ -116,7 +947,7 @@ fn parsing_info() {
 			}
 			debug_string.push_str(")");
 
-			$name($($arg),*).unwrap_or_else(|| panic!(debug_string))
+			$name($($arg),*).unwrap_or_else(|| panic!("{}", debug_string))
 		}};
 	}
 
@@ -133,13 +964,12 @@ fn parsing_info() {
 		.collect();
 
 	for (pos, range) in positions {
-		use std::iter;
 		println!(
 			"{eq}\n{spaces_before}{arrows}{spaces_after} - {position:?}",
 			eq = string,
-			spaces_before = iter::repeat(' ').take(range.start.0).collect::<String>(),
-			arrows = iter::repeat('^').take(range.end.0 - range.start.0).collect::<String>(),
-			spaces_after = iter::repeat(' ').take(string.len() - range.end.0).collect::<String>(),
+			spaces_before = " ".repeat(range.start.0),
+			arrows = "^".repeat(range.end.0 - range.start.0),
+			spaces_after = " ".repeat(string.len() - range.end.0),
 			position = pos,
 		)
 	}